@@ -12,6 +12,13 @@ pub trait WriteBytesExt: std::io::Write {
         self.write_all(&buf)?;
         Ok(())
     }
+
+    #[inline]
+    fn write_le_u32(&mut self, v: u32) -> std::io::Result<()> {
+        let buf = v.to_le_bytes();
+        self.write_all(&buf)?;
+        Ok(())
+    }
 }
 
 impl<W: std::io::Write> WriteBytesExt for W {}
@@ -38,6 +45,39 @@ pub trait ReadBytesExt: std::io::Read {
         Ok(u32::from_le_bytes(buf))
     }
 
+    /// Like `read_exact`, but returns `false` instead of an error if the
+    /// reader is already at its end. Running out of data after some of
+    /// `buf` was read is still an `UnexpectedEof` error.
+    fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> std::io::Result<bool> {
+        let mut bytes_read = 0;
+
+        while bytes_read < buf.len() {
+            match self.read(&mut buf[bytes_read..]) {
+                Ok(0) if bytes_read == 0 => return Ok(false),
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => bytes_read += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(true)
+    }
+
+    #[inline]
+    fn read_u8_opt(&mut self) -> std::io::Result<Option<u8>> {
+        let mut buf = [0; 1];
+        Ok(self.read_exact_or_eof(&mut buf)?.then_some(buf[0]))
+    }
+
+    #[inline]
+    fn read_le_u16_opt(&mut self) -> std::io::Result<Option<u16>> {
+        let mut buf = [0; 2];
+        Ok(self
+            .read_exact_or_eof(&mut buf)?
+            .then(|| u16::from_le_bytes(buf)))
+    }
+
     fn read_fixed_str(&mut self, len: usize) -> std::io::Result<String> {
         let mut s = String::new();
         let mut bytes_read = 0;
@@ -60,3 +100,18 @@ pub trait ReadBytesExt: std::io::Read {
 }
 
 impl<R: std::io::Read> ReadBytesExt for R {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_le_u16_opt_distinguishes_truncation_from_eof() {
+        assert_eq!((&[0x34, 0x12][..]).read_le_u16_opt().unwrap(), Some(0x1234));
+        assert_eq!((&[][..]).read_le_u16_opt().unwrap(), None);
+        assert_eq!(
+            (&[0x34][..]).read_le_u16_opt().unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+}
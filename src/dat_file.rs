@@ -1,15 +1,27 @@
 use std::{
     fs::File,
-    io::{BufReader, Cursor, Read, Seek},
+    io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom},
     path::PathBuf,
 };
 
 use crate::error::Error;
-use crate::{bytes_ext::ReadBytesExt, unhsq::unhsq};
+use crate::{
+    bytes_ext::ReadBytesExt,
+    unhsq::{unhsq, unhsq_with_stats, HsqStats},
+};
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
 
 pub struct DatFile {
-    reader: BufReader<File>,
+    reader: Box<dyn ReadSeek>,
     pub entries: Vec<DatEntry>,
+    /// Decompress resources with a well-formed HSQ header even if the
+    /// header checksum is wrong
+    pub ignore_hsq_checksum: bool,
+    table_end: usize,
+    file_size: usize,
 }
 
 #[derive(Debug)]
@@ -19,8 +31,53 @@ pub struct DatEntry {
     pub size: usize,
 }
 
+/// The layout of a record in the entry table. Records hold a NUL-padded
+/// name followed by the size and offset of the entry, and in the original
+/// DUNE.DAT a trailing byte.
+pub struct RecordLayout {
+    pub name_length: usize,
+    pub trailing_byte: bool,
+}
+
+impl Default for RecordLayout {
+    fn default() -> Self {
+        RecordLayout {
+            name_length: 16,
+            trailing_byte: true,
+        }
+    }
+}
+
+/// Where an entry is stored, as listed by `DatFile::layout_report`.
+#[derive(Debug)]
+pub struct LayoutEntry {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    pub end: usize,
+    /// Bytes between the end of the entries stored before this one and its
+    /// start, negative if they overlap
+    pub gap: isize,
+}
+
+#[derive(Debug)]
+pub struct LayoutReport {
+    /// The entries sorted by offset
+    pub entries: Vec<LayoutEntry>,
+    /// Bytes after the last entry, negative if an entry extends past the
+    /// end of the file
+    pub trailing: isize,
+}
+
+#[derive(Debug)]
+pub struct EntryInfo {
+    pub raw_size: usize,
+    pub compressed: bool,
+    pub unpacked_size: Option<usize>,
+}
+
 impl DatFile {
-    pub fn open(path: &Option<PathBuf>) -> Result<DatFile, Error> {
+    pub fn open(path: &Option<PathBuf>, layout: &RecordLayout) -> Result<DatFile, Error> {
         let path = match path {
             Some(p) if p.is_dir() => {
                 let mut p = p.clone();
@@ -32,15 +89,33 @@ impl DatFile {
         };
 
         let file = File::open(&path)?;
-        let mut reader = BufReader::new(file);
+        Self::from_reader(BufReader::new(file), layout)
+    }
+
+    pub fn from_reader<R: Read + Seek + 'static>(
+        mut reader: R,
+        layout: &RecordLayout,
+    ) -> Result<DatFile, Error> {
+        let record_length = layout.name_length + 8 + layout.trailing_byte as usize;
 
         let entry_count = reader.read_le_u16()? as usize;
         let mut entries = Vec::with_capacity(entry_count);
         for _ in 0..entry_count {
-            let name = reader.read_fixed_str(16)?;
-            let size = reader.read_le_u32()? as usize;
-            let offset = reader.read_le_u32()? as usize;
-            _ = reader.read_u8();
+            let mut record = vec![0; record_length];
+            if let Err(e) = reader.read_exact(&mut record) {
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    return Err(Error::TruncatedDatFile {
+                        entries_read: entries.len(),
+                        entry_count,
+                    });
+                }
+                return Err(e.into());
+            }
+
+            let mut record = Cursor::new(&record[..]);
+            let name = record.read_fixed_str(layout.name_length)?;
+            let size = record.read_le_u32()? as usize;
+            let offset = record.read_le_u32()? as usize;
 
             if name.is_empty() {
                 break;
@@ -49,56 +124,398 @@ impl DatFile {
             entries.push(DatEntry { name, size, offset });
         }
 
-        Ok(DatFile { reader, entries })
+        let table_end = reader.stream_position()? as usize;
+        let file_size = reader.seek(SeekFrom::End(0))? as usize;
+
+        // A table read with the wrong record layout yields garbage offsets
+        if entries
+            .iter()
+            .any(|e| e.offset < table_end || e.offset > file_size)
+        {
+            return Err(Error::InvalidDatFile);
+        }
+
+        Ok(DatFile {
+            reader: Box::new(reader),
+            entries,
+            ignore_hsq_checksum: false,
+            table_end,
+            file_size,
+        })
     }
 
-    pub fn read_raw(&mut self, name: &str) -> Result<Vec<u8>, Error> {
-        let entry = self
+    /// Returns the names of the entries in the order they are stored.
+    /// Reading needs `&mut self`, so collect the names first to read every
+    /// entry.
+    pub fn entry_names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.name.as_str())
+    }
+
+    /// Lists the entries by offset with the space between them, starting
+    /// from the end of the entry table.
+    pub fn layout_report(&self) -> LayoutReport {
+        let mut entries = self
             .entries
+            .iter()
+            .map(|e| LayoutEntry {
+                name: e.name.clone(),
+                offset: e.offset,
+                size: e.size,
+                end: e.offset + e.size,
+                gap: 0,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|e| e.offset);
+
+        let mut end = self.table_end;
+        for e in entries.iter_mut() {
+            e.gap = e.offset as isize - end as isize;
+            end = end.max(e.end);
+        }
+
+        LayoutReport {
+            entries,
+            trailing: self.file_size as isize - end as isize,
+        }
+    }
+
+    fn entry(&self, name: &str) -> Result<&DatEntry, Error> {
+        self.entries
             .iter()
             .find(|&e| e.name == name)
-            .ok_or(Error::EntryNotFound)?;
+            .ok_or(Error::EntryNotFound)
+    }
+
+    pub fn read_raw(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let entry = self.entry(name)?;
+        let (offset, size) = (entry.offset, entry.size);
 
-        self.reader
-            .seek(std::io::SeekFrom::Start(entry.offset as u64))?;
+        self.reader.seek(std::io::SeekFrom::Start(offset as u64))?;
 
-        let mut data = vec![0; entry.size];
+        let mut data = vec![0; size];
         self.reader.read_exact(data.as_mut_slice())?;
 
         Ok(data)
     }
 
+    /// Returns the size of an entry, and its unpacked size if it is
+    /// compressed, reading only the compression header.
+    pub fn entry_info(&mut self, name: &str) -> Result<EntryInfo, Error> {
+        let entry = self.entry(name)?;
+        let (offset, size) = (entry.offset, entry.size);
+
+        self.reader.seek(std::io::SeekFrom::Start(offset as u64))?;
+
+        let mut header = vec![0; size.min(6)];
+        self.reader.read_exact(header.as_mut_slice())?;
+
+        let mut info = EntryInfo {
+            raw_size: size,
+            compressed: false,
+            unpacked_size: None,
+        };
+
+        if let Some(header) = self.compression_header(name, &header, size)? {
+            if header.packed_length as usize == size {
+                info.compressed = true;
+                info.unpacked_size = Some(header.unpacked_length as usize);
+            }
+        }
+
+        Ok(info)
+    }
+
     pub fn read(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        Ok(self.read_with_info(name)?.0)
+    }
+
+    /// Reads an entry like `read`, also returning whether it was
+    /// decompressed.
+    pub fn read_with_info(&mut self, name: &str) -> Result<(Vec<u8>, EntryInfo), Error> {
         let data = self.read_raw(name)?;
 
-        if !is_compressed(&data) {
-            return Ok(data);
+        let mut info = EntryInfo {
+            raw_size: data.len(),
+            compressed: false,
+            unpacked_size: None,
+        };
+
+        let Some(header) = self.compression_header(name, &data, data.len())? else {
+            return Ok((data, info));
+        };
+
+        if header.packed_length as usize != data.len() {
+            eprintln!("Packed length does not match resource size");
+            return Ok((data, info));
+        }
+
+        let mut unpacked_data = vec![0; header.unpacked_length as usize];
+
+        unhsq(&data[6..], &mut unpacked_data)?;
+
+        info.compressed = true;
+        info.unpacked_size = Some(unpacked_data.len());
+        Ok((unpacked_data, info))
+    }
+
+    /// Decompresses an entry and returns statistics about its HSQ stream,
+    /// or `None` if the entry isn't compressed.
+    pub fn hsq_stats(&mut self, name: &str) -> Result<Option<HsqStats>, Error> {
+        let data = self.read_raw(name)?;
+
+        let Some(header) = self.compression_header(name, &data, data.len())? else {
+            return Ok(None);
+        };
+
+        if header.packed_length as usize != data.len() {
+            return Ok(None);
         }
 
-        let mut reader = Cursor::new(&data);
+        let mut unpacked_data = vec![0; header.unpacked_length as usize];
+
+        Ok(Some(unhsq_with_stats(&data[6..], &mut unpacked_data)?))
+    }
+
+    /// Returns the compression header of an entry if it should be
+    /// decompressed, given at least its first six bytes and its size.
+    fn compression_header(
+        &self,
+        name: &str,
+        data: &[u8],
+        size: usize,
+    ) -> Result<Option<HsqHeader>, Error> {
+        if is_compressed(data, true) {
+            return Ok(Some(HsqHeader::read(data)?));
+        }
+
+        if self.ignore_hsq_checksum && is_compressed(data, false) {
+            let header = HsqHeader::read(data)?;
+            if header.packed_length as usize == size {
+                eprintln!("Warning: `{}` has an invalid HSQ checksum", name);
+                return Ok(Some(header));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Checks that an `.HSQ` entry has a valid compression header whose
+    /// packed length matches the stored size, and that it decompresses.
+    pub fn verify(&mut self, name: &str) -> Result<(), Error> {
+        let data = self.read_raw(name)?;
+
+        if !name.ends_with(".HSQ") {
+            return Ok(());
+        }
+
+        if !is_compressed(&data, true) {
+            return Err(Error::InvalidHsqHeader);
+        }
+
+        let header = HsqHeader::read(&data)?;
+        if header.packed_length as usize != data.len() {
+            return Err(Error::PackedLengthMismatch {
+                packed_length: header.packed_length as usize,
+                size: data.len(),
+            });
+        }
+
+        let mut unpacked_data = vec![0; header.unpacked_length as usize];
+        unhsq(&data[6..], &mut unpacked_data)?;
+
+        Ok(())
+    }
+}
+
+struct HsqHeader {
+    unpacked_length: u16,
+    packed_length: u16,
+}
+
+impl HsqHeader {
+    fn read(data: &[u8]) -> Result<HsqHeader, Error> {
+        let mut reader = Cursor::new(data);
         let unpacked_length = reader.read_le_u16()?;
         _ = reader.read_u8();
         let packed_length = reader.read_le_u16()?;
         _ = reader.read_u8();
 
-        if packed_length as usize != data.len() {
-            println!("Packed length does not match resource size");
-            return Ok(data);
-        }
-
-        let mut unpacked_data = vec![0; unpacked_length as usize];
-
-        unhsq(&data[6..], &mut unpacked_data);
-        Ok(unpacked_data)
+        Ok(HsqHeader {
+            unpacked_length,
+            packed_length,
+        })
     }
 }
 
-fn is_compressed(header: &[u8]) -> bool {
+fn is_compressed(header: &[u8], check_checksum: bool) -> bool {
     if header.len() < 6 {
         return false;
     }
 
     let checksum: u8 = header.iter().take(6).fold(0, |acc, &x| acc.wrapping_add(x));
 
-    checksum == 0xab && header[2] == 0
+    (checksum == 0xab || !check_checksum) && header[2] == 0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::unhsq::tests::{hsq, truncate_hsq};
+
+    /// Builds a DAT holding `entries`, with the terminating empty record
+    /// counted in the header like the original.
+    pub(crate) fn dat(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        dat_with_layout(entries, &RecordLayout::default())
+    }
+
+    fn dat_with_layout(entries: &[(&str, &[u8])], layout: &RecordLayout) -> Vec<u8> {
+        let name_length = layout.name_length;
+        let record_length = name_length + 8 + layout.trailing_byte as usize;
+        let mut offset = 2 + record_length * (entries.len() + 1);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(entries.len() as u16 + 1).to_le_bytes());
+        for (name, data) in entries {
+            let mut record = vec![0; record_length];
+            record[..name.len()].copy_from_slice(name.as_bytes());
+            record[name_length..name_length + 4]
+                .copy_from_slice(&(data.len() as u32).to_le_bytes());
+            record[name_length + 4..name_length + 8]
+                .copy_from_slice(&(offset as u32).to_le_bytes());
+            out.extend_from_slice(&record);
+            offset += data.len();
+        }
+        out.resize(out.len() + record_length, 0);
+        for (_, data) in entries {
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    pub(crate) fn dat_file(entries: &[(&str, &[u8])]) -> DatFile {
+        DatFile::from_reader(Cursor::new(dat(entries)), &RecordLayout::default()).unwrap()
+    }
+
+    #[test]
+    fn reads_entries_from_memory() {
+        let mut dat_file = dat_file(&[("A.BIN", b"hello"), ("SUB\\B.BIN", b"xy")]);
+
+        assert_eq!(
+            dat_file.entry_names().collect::<Vec<_>>(),
+            ["A.BIN", "SUB\\B.BIN"]
+        );
+        assert_eq!(dat_file.read("A.BIN").unwrap(), b"hello");
+        assert_eq!(dat_file.read("SUB\\B.BIN").unwrap(), b"xy");
+        assert!(matches!(dat_file.read("C.BIN"), Err(Error::EntryNotFound)));
+    }
+
+    #[test]
+    fn decompresses_hsq_entries() {
+        let packed = hsq(b"hello world");
+        let mut dat_file = dat_file(&[("A.HSQ", &packed)]);
+
+        let (data, info) = dat_file.read_with_info("A.HSQ").unwrap();
+        assert_eq!(data, b"hello world");
+        assert!(info.compressed);
+        assert_eq!(info.raw_size, packed.len());
+        assert_eq!(info.unpacked_size, Some(11));
+        assert_eq!(dat_file.read_raw("A.HSQ").unwrap(), packed);
+        assert!(dat_file.verify("A.HSQ").is_ok());
+    }
+
+    #[test]
+    fn entry_info_reads_unpacked_size_from_header() {
+        let packed = hsq(b"hello world");
+        let mut dat_file = dat_file(&[("A.HSQ", &packed), ("B.BIN", b"xy")]);
+
+        let info = dat_file.entry_info("A.HSQ").unwrap();
+        assert!(info.compressed);
+        assert_eq!(info.raw_size, packed.len());
+        assert_eq!(
+            info.unpacked_size,
+            Some(dat_file.read("A.HSQ").unwrap().len())
+        );
+
+        let info = dat_file.entry_info("B.BIN").unwrap();
+        assert!(!info.compressed);
+        assert_eq!(info.raw_size, 2);
+        assert_eq!(info.unpacked_size, None);
+    }
+
+    #[test]
+    fn ignore_hsq_checksum_decompresses_bad_checksum() {
+        let mut packed = hsq(b"hello world");
+        packed[5] = packed[5].wrapping_add(1);
+        let mut dat_file = dat_file(&[("A.HSQ", &packed)]);
+
+        assert_eq!(dat_file.read("A.HSQ").unwrap(), packed);
+        assert!(!dat_file.entry_info("A.HSQ").unwrap().compressed);
+
+        dat_file.ignore_hsq_checksum = true;
+        assert_eq!(dat_file.read("A.HSQ").unwrap(), b"hello world");
+        assert!(dat_file.entry_info("A.HSQ").unwrap().compressed);
+    }
+
+    #[test]
+    fn verify_rejects_truncated_hsq_stream() {
+        let mut packed = hsq(b"hello world");
+        truncate_hsq(&mut packed, 10);
+
+        let mut dat_file = dat_file(&[("A.HSQ", &packed)]);
+        assert!(matches!(
+            dat_file.verify("A.HSQ"),
+            Err(Error::InvalidHsqData("truncated stream"))
+        ));
+    }
+
+    #[test]
+    fn reads_entries_without_trailing_byte() {
+        let layout = RecordLayout {
+            trailing_byte: false,
+            ..RecordLayout::default()
+        };
+        let data = dat_with_layout(&[("A.BIN", b"hello"), ("B.BIN", b"xy")], &layout);
+
+        let mut dat_file = DatFile::from_reader(Cursor::new(data.clone()), &layout).unwrap();
+        assert_eq!(dat_file.read("A.BIN").unwrap(), b"hello");
+        assert_eq!(dat_file.read("B.BIN").unwrap(), b"xy");
+
+        // Read with the wrong layout, the offsets land outside the data
+        let result = DatFile::from_reader(Cursor::new(data), &RecordLayout::default());
+        assert!(matches!(
+            result,
+            Err(Error::InvalidDatFile | Error::TruncatedDatFile { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_entry_table() {
+        let mut data = dat(&[("A.BIN", b"a"), ("B.BIN", b"b")]);
+        data.truncate(2 + 25 + 10);
+
+        let result = DatFile::from_reader(Cursor::new(data), &RecordLayout::default());
+        assert!(matches!(
+            result,
+            Err(Error::TruncatedDatFile {
+                entries_read: 1,
+                entry_count: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn layout_report_flags_overlaps() {
+        let mut data = dat(&[("A.BIN", b"aaaa"), ("B.BIN", b"bbbb")]);
+        // Move B two bytes back into A
+        let field = 2 + 25 + 20..2 + 25 + 24;
+        let b_offset = u32::from_le_bytes(data[field.clone()].try_into().unwrap());
+        data[field].copy_from_slice(&(b_offset - 2).to_le_bytes());
+
+        let dat_file = DatFile::from_reader(Cursor::new(data), &RecordLayout::default()).unwrap();
+        let report = dat_file.layout_report();
+
+        assert_eq!(report.entries[0].gap, 0);
+        assert_eq!(report.entries[1].gap, -2);
+        assert_eq!(report.trailing, 2);
+    }
 }
@@ -2,10 +2,27 @@
 #[allow(clippy::enum_variant_names)]
 pub enum Error {
     EntryNotFound,
-    // InvalidDatFile,
+    InvalidHsqHeader,
+    PackedLengthMismatch {
+        packed_length: usize,
+        size: usize,
+    },
+    InvalidHsqData(&'static str),
+    InvalidDatFile,
+    TruncatedDatFile {
+        entries_read: usize,
+        entry_count: usize,
+    },
     IOError(std::io::Error),
     // SpriteTOCError,
+    InvalidSpriteSheet(&'static str),
+    InvalidSpriteIndex(usize),
+    MissingPalette,
+    InvalidOutTemplate(&'static str),
+    InvalidVocFile(&'static str),
+    InvalidSaveFile(&'static str),
     PNGEncodingError(png::EncodingError),
+    GIFEncodingError(gif::EncodingError),
 }
 
 impl From<std::io::Error> for Error {
@@ -20,14 +37,45 @@ impl From<png::EncodingError> for Error {
     }
 }
 
+impl From<gif::EncodingError> for Error {
+    fn from(e: gif::EncodingError) -> Self {
+        Self::GIFEncodingError(e)
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::EntryNotFound => write!(f, "entry not found"),
-            // Error::InvalidDatFile => write!(f, "invalid dat file"),
+            Error::InvalidHsqHeader => write!(f, "invalid hsq header"),
+            Error::PackedLengthMismatch {
+                packed_length,
+                size,
+            } => write!(
+                f,
+                "packed length {} does not match resource size {}",
+                packed_length, size
+            ),
+            Error::InvalidHsqData(e) => write!(f, "invalid hsq data: {}", e),
+            Error::InvalidDatFile => write!(f, "invalid dat file"),
+            Error::TruncatedDatFile {
+                entries_read,
+                entry_count,
+            } => write!(
+                f,
+                "dat file truncated after {} of {} entries",
+                entries_read, entry_count
+            ),
             Error::IOError(e) => write!(f, "{}", e),
             // Error::SpriteTOCError => write!(f, "error reading sprite toc"),
+            Error::InvalidSpriteSheet(e) => write!(f, "invalid sprite sheet: {}", e),
+            Error::InvalidSpriteIndex(i) => write!(f, "invalid sprite index {}", i),
+            Error::MissingPalette => write!(f, "sprite sheet has no palette"),
+            Error::InvalidOutTemplate(e) => write!(f, "invalid output template: {}", e),
+            Error::InvalidVocFile(e) => write!(f, "invalid voc file: {}", e),
+            Error::InvalidSaveFile(e) => write!(f, "invalid save file: {}", e),
             Error::PNGEncodingError(e) => write!(f, "{}", e),
+            Error::GIFEncodingError(e) => write!(f, "{}", e),
         }
     }
 }
@@ -0,0 +1,119 @@
+use std::io::{self, Cursor, Read};
+
+pub const GLYPH_WIDTH: usize = 8;
+
+pub struct FontBank {
+    pub height: usize,
+    glyphs: Vec<u8>,
+}
+
+impl FontBank {
+    fn read(r: &mut Cursor<&[u8]>, offset: usize, height: usize) -> io::Result<FontBank> {
+        r.set_position(offset as u64);
+
+        let mut glyphs = vec![0; 128 * height];
+        r.read_exact(&mut glyphs)?;
+
+        Ok(FontBank { height, glyphs })
+    }
+
+    /// Draws a glyph in white into an RGBA image `w` pixels wide, clipping
+    /// at the image edges.
+    pub fn draw_glyph(&self, dst: &mut [u8], w: usize, x: usize, y: usize, glyph: usize) {
+        let h = dst.len() / (4 * w);
+
+        for dy in 0..self.height {
+            let bs = self.glyphs[self.height * glyph + dy];
+            for dx in 0..GLYPH_WIDTH {
+                if (bs << dx) & 0x80 == 0x80 && x + dx < w && y + dy < h {
+                    dst[4 * ((y + dy) * w + (x + dx)) + 0] = 255;
+                    dst[4 * ((y + dy) * w + (x + dx)) + 1] = 255;
+                    dst[4 * ((y + dy) * w + (x + dx)) + 2] = 255;
+                    dst[4 * ((y + dy) * w + (x + dx)) + 3] = 255;
+                }
+            }
+        }
+    }
+}
+
+/// Where the two glyph banks of a font resource are stored. The first bank
+/// always follows the 256-byte glyph width table.
+pub struct FontLayout {
+    pub bank1_height: usize,
+    pub bank2_height: usize,
+    pub bank2_offset: usize,
+}
+
+impl Default for FontLayout {
+    fn default() -> Self {
+        FontLayout {
+            bank1_height: 9,
+            bank2_height: 7,
+            bank2_offset: 0x100 + 128 * 9,
+        }
+    }
+}
+
+/// A font resource: a table of 256 glyph widths followed by two banks of
+/// 128 glyphs each.
+pub struct Font {
+    pub widths: [u8; 256],
+    pub banks: [FontBank; 2],
+}
+
+impl Font {
+    pub fn new(data: &[u8], layout: &FontLayout) -> io::Result<Font> {
+        let mut r = Cursor::new(data);
+
+        let mut widths = [0; 256];
+        r.read_exact(&mut widths)?;
+
+        let bank1 = FontBank::read(&mut r, 0x100, layout.bank1_height)?;
+        let bank2 = FontBank::read(&mut r, layout.bank2_offset, layout.bank2_height)?;
+
+        Ok(Font {
+            widths,
+            banks: [bank1, bank2],
+        })
+    }
+
+    /// Returns the bank and the index within it of the glyph for `c`.
+    pub fn glyph(&self, c: u8) -> (&FontBank, usize) {
+        let c = c as usize;
+        (&self.banks[c / 128], c % 128)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_banks_with_custom_layout() {
+        let layout = FontLayout {
+            bank1_height: 10,
+            bank2_height: 6,
+            bank2_offset: 0x100 + 128 * 10 + 16,
+        };
+        let mut data = vec![0; layout.bank2_offset + 128 * 6];
+        data[0x100 + 10 * 5] = 0x80;
+        data[layout.bank2_offset + 6 * 3 + 5] = 0x01;
+
+        let font = Font::new(&data, &layout).unwrap();
+        assert_eq!(font.banks[0].height, 10);
+        assert_eq!(font.banks[1].height, 6);
+
+        let mut dst = vec![0; 8 * 10 * 4];
+        font.banks[0].draw_glyph(&mut dst, 8, 0, 0, 5);
+        assert_eq!(dst[3], 255);
+        assert_eq!(dst.iter().filter(|&&c| c != 0).count(), 4);
+
+        let mut dst = vec![0; 8 * 6 * 4];
+        let (bank, glyph) = font.glyph(128 + 3);
+        bank.draw_glyph(&mut dst, 8, 0, 0, glyph);
+        assert_eq!(dst[4 * (5 * 8 + 7) + 3], 255);
+        assert_eq!(dst.iter().filter(|&&c| c != 0).count(), 4);
+
+        assert!(Font::new(&data[..data.len() - 1], &layout).is_err());
+    }
+}
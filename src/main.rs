@@ -3,23 +3,32 @@
 mod bytes_ext;
 mod dat_file;
 mod error;
+mod font;
+mod pal;
+mod sav;
 mod sprite;
 mod unhsq;
+mod voc;
 
 use std::{
+    collections::HashMap,
     fs::{self, File},
-    io::{self, BufWriter, Cursor, Read, Write},
-    path::{Path, PathBuf},
-    slice,
+    io::{self, BufWriter, Cursor, Write},
+    path::{Component, Path, PathBuf},
+    process,
 };
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use itertools::Itertools;
 
 use crate::{
-    bytes_ext::{ReadBytesExt, WriteBytesExt},
-    dat_file::DatFile,
+    bytes_ext::ReadBytesExt,
+    dat_file::{DatFile, EntryInfo, RecordLayout},
     error::Error,
+    font::{Font, FontLayout},
+    sav::SavFile,
+    sprite::SpriteHeader,
+    voc::Sound,
 };
 
 #[derive(Debug, Parser)]
@@ -29,6 +38,16 @@ struct Cli {
     dat_path: Option<PathBuf>,
     #[arg(long, default_value = "dump")]
     out_path: PathBuf,
+    /// Layout of the DUNE.DAT entry table
+    #[arg(long, value_enum, default_value_t = DatVariant::Standard)]
+    dat_variant: DatVariant,
+    /// Decompress resources with an invalid HSQ checksum
+    #[arg(long)]
+    ignore_hsq_checksum: bool,
+    /// Write all files directly into the output directory, replacing path
+    /// separators in resource names with `_`
+    #[arg(long)]
+    flat: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -36,170 +55,434 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// List the contents of DUNE.DAT
-    List,
+    List {
+        /// Also show the unpacked size of compressed resources
+        #[arg(long)]
+        verbose: bool,
+        /// Sort the resources by this key instead of their order in DUNE.DAT
+        #[arg(long, value_enum)]
+        sort: Option<SortKey>,
+        /// Sort in descending order
+        #[arg(long, requires = "sort")]
+        desc: bool,
+        /// Only list resources whose name contains this, ignoring case
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Checks that every resource in DUNE.DAT can be read
+    VerifyDat {
+        /// Also list where each resource is stored, flagging resources that
+        /// overlap or leave gaps
+        #[arg(long)]
+        layout: bool,
+    },
     /// Decompress RLE-compressed save file
     DecompressSav { file_name: String },
     /// Recompress save file
     CompressSav { file_name: String },
     /// Extracts all resource from DUNE.DAT, decompressing if needed
-    ExtractAll,
+    ExtractAll {
+        /// Print what would be written without creating any files
+        #[arg(long)]
+        dry_run: bool,
+        /// Write a JSON manifest of the extracted resources to this file
+        #[arg(long, conflicts_with = "dry_run")]
+        manifest: Option<PathBuf>,
+        /// Also extract the sprites of resources that look like sprite sheets
+        #[arg(long, conflicts_with = "dry_run")]
+        recursive: bool,
+        /// Keep the `.HSQ` extension of decompressed resources
+        #[arg(long)]
+        keep_ext: bool,
+    },
     /// Extracts a resource from DUNE.DAT without decompressing
-    ExtractRaw { entry_name: String },
+    ExtractRaw {
+        entry_name: String,
+        /// Write the resource to stdout instead of a file
+        #[arg(long)]
+        stdout: bool,
+    },
     /// Extracts a resource from DUNE.DAT, decompressing if needed
-    Extract { entry_name: String },
+    Extract {
+        entry_name: String,
+        /// Print what would be written without creating any files
+        #[arg(long)]
+        dry_run: bool,
+        /// Write the resource to stdout instead of a file
+        #[arg(long, conflicts_with = "dry_run")]
+        stdout: bool,
+        /// Keep the `.HSQ` extension of a decompressed resource
+        #[arg(long, conflicts_with = "stdout")]
+        keep_ext: bool,
+    },
     /// Extracts sprite resources from a sprite sheet
-    ExtractSprites { entry_name: String },
+    ExtractSprites {
+        entry_name: String,
+        #[command(flatten)]
+        options: SpriteOptions,
+    },
+    /// Prints the size and encoding of each sprite in a sprite sheet
+    SpriteInfo {
+        entry_name: String,
+        /// Print the sprites as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
     /// Extracts font resource
-    ExtractFont { entry_name: String },
+    ExtractFont {
+        entry_name: String,
+        /// Also write the glyph widths to `{stem}.widths.json`
+        #[arg(long)]
+        widths: bool,
+        /// Height of the glyphs in the first bank
+        #[arg(long, default_value_t = FontLayout::default().bank1_height)]
+        bank1_height: usize,
+        /// Height of the glyphs in the second bank
+        #[arg(long, default_value_t = FontLayout::default().bank2_height)]
+        bank2_height: usize,
+        /// Offset of the second bank in the resource
+        #[arg(long, default_value_t = FontLayout::default().bank2_offset)]
+        bank2_offset: usize,
+    },
+    /// Prints statistics about the compressed stream of a resource
+    DumpHsqStats { entry_name: String },
+    /// Extracts a sound resource as a WAV file
+    ExtractSound {
+        entry_name: String,
+        /// Sample rate of resources that are raw PCM rather than VOC
+        #[arg(long, default_value_t = 11025)]
+        sample_rate: u32,
+    },
+    /// Renders a line of text with a font resource
+    RenderText { font_entry: String, text: String },
+    /// Extracts a resource as images or sound depending on what it looks
+    /// like, or prints a hex dump if its type can't be detected
+    Render { entry_name: String },
 }
 
-fn create_file_for_entry(path: &Path, entry_name: &str) -> io::Result<File> {
-    let path = path.join(entry_name.split('\\').collect::<PathBuf>());
-    if let Some(dir) = path.parent() {
-        fs::create_dir_all(dir)?;
-    }
-    File::create(path)
+#[derive(Debug, Default, Args)]
+struct SpriteOptions {
+    /// Only extract the sprite with this index
+    #[arg(long)]
+    index: Option<usize>,
+    /// Draw 4bpp sprites with this palette offset instead of their own
+    #[arg(long)]
+    pal_offset: Option<u8>,
+    /// Mirror sprites horizontally
+    #[arg(long)]
+    flip_x: bool,
+    /// Mirror sprites vertically
+    #[arg(long)]
+    flip_y: bool,
+    /// Sprite pixel value to treat as transparent. Defaults to 0 for 4bpp
+    /// sprites and 8bpp sprites with palette offset 255; other 8bpp sprites
+    /// are drawn opaque
+    #[arg(long)]
+    transparent: Option<u8>,
+    /// Draw every pixel, with no transparent value
+    #[arg(long, conflicts_with = "transparent")]
+    opaque: bool,
+    /// Write the sprites as the frames of an animated GIF instead of PNG files
+    #[arg(long)]
+    gif: Option<PathBuf>,
+    /// Delay between GIF frames in hundredths of a second
+    #[arg(long, default_value_t = 10)]
+    delay: u16,
+    /// Fail if the sheet has no palette instead of using a grayscale one
+    #[arg(long)]
+    require_palette: bool,
+    /// Decode sprites with this many bits per pixel instead of guessing
+    /// from their palette offset
+    #[arg(long, value_enum)]
+    bpp: Option<Bpp>,
+    /// Draw palette index FROM with the color of index TO, given as
+    /// `FROM:TO`; may be repeated
+    #[arg(long, value_name = "FROM:TO", value_parser = parse_remap)]
+    remap: Vec<(u8, u8)>,
+    /// Skip sprites whose offset isn't below the next one instead of
+    /// rejecting the sheet
+    #[arg(long)]
+    lenient: bool,
+    /// Name the PNG files after this template instead of `{stem}-{index}.png`.
+    /// `{stem}`, `{index}`, `{width}` and `{height}` are replaced with the
+    /// sheet name, the zero-padded sprite index and the sprite size
+    #[arg(long, conflicts_with = "gif")]
+    out_template: Option<String>,
+    /// Also write the palette the sprites are drawn with to `{stem}.gpl`
+    #[arg(long)]
+    palette: bool,
 }
 
-fn list(dat_file: &mut DatFile) {
-    println!("+------------------+------------+------------+");
-    println!("| name             |     offset |       size |");
-    println!("+------------------+------------+------------+");
-    for e in dat_file.entries.iter() {
-        println!("| {:16} | {:-10} | {:-10} |", e.name, e.offset, e.size);
-    }
-    println!("+------------------+------------+------------+");
+fn parse_remap(s: &str) -> Result<(u8, u8), String> {
+    let (from, to) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected `FROM:TO`, got `{}`", s))?;
+    let from = from
+        .parse()
+        .map_err(|e| format!("invalid index `{}`: {}", from, e))?;
+    let to = to
+        .parse()
+        .map_err(|e| format!("invalid index `{}`: {}", to, e))?;
+    Ok((from, to))
 }
 
-fn decompress_sav(file_name: &str) -> Result<(), Error> {
-    let mut file = File::open(file_name)?;
-
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
-
-    let data_len = data.len();
-
-    let mut r = Cursor::new(data);
-    let mut w = Vec::<u8>::new();
-
-    let unk0 = r.read_le_u16()?;
-    let rle_word = r.read_le_u16()?;
-    // The length includes the rle-word and itself but not the first word
-    let length = r.read_le_u16()? as usize;
-
-    let rle_byte = rle_word as u8;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DatVariant {
+    /// 16 byte names and a trailing byte, as in the original release
+    Standard,
+    /// 16 byte names without the trailing byte
+    NoTrailer,
+}
 
-    if length != data_len - 2 {
-        println!(
-            "`{}` is not a Dune save file - invalid length in header.",
-            file_name
-        );
-        return Ok(());
+impl DatVariant {
+    fn record_layout(self) -> RecordLayout {
+        match self {
+            DatVariant::Standard => RecordLayout::default(),
+            DatVariant::NoTrailer => RecordLayout {
+                trailing_byte: false,
+                ..RecordLayout::default()
+            },
+        }
     }
+}
 
-    w.write_le_u16(unk0)?;
-    w.write_le_u16(rle_word)?;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Bpp {
+    #[value(name = "4")]
+    Four,
+    #[value(name = "8")]
+    Eight,
+}
 
-    while let Ok(c) = r.read_u8() {
-        if c == rle_byte {
-            let cnt = r.read_u8()?;
-            let val = r.read_u8()?;
-            for _ in 0..cnt {
-                w.write_all(slice::from_ref(&val))?;
-            }
+impl SpriteOptions {
+    /// Returns the pixel value to skip when drawing a sprite, if any.
+    fn transparent(&self, header: &SpriteHeader, is_4bpp: bool) -> Option<u8> {
+        if self.opaque {
+            None
+        } else if self.transparent.is_some() {
+            self.transparent
         } else {
-            w.write_all(slice::from_ref(&c))?;
+            (is_4bpp || header.pal_offset == 255).then_some(0)
         }
     }
+}
 
-    let out_file_name: String = file_name
-        .strip_suffix(".SAV")
-        .unwrap_or(file_name)
-        .to_owned()
-        + ".BIN";
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SortKey {
+    Name,
+    Offset,
+    Size,
+}
 
-    let mut out_file = File::create(&out_file_name)?;
-    out_file.write_all(&w)?;
+/// The directory extracted resources are written to.
+struct OutDir {
+    path: PathBuf,
+    /// Replace path separators in resource names with underscores instead
+    /// of creating subdirectories
+    flat: bool,
+}
 
-    println!("Decompressed `{}` to `{}`", file_name, out_file_name);
+impl OutDir {
+    fn path_for_entry(&self, entry_name: &str) -> io::Result<PathBuf> {
+        let entry_path = entry_name.split('\\').collect::<PathBuf>();
+
+        // Keep extraction confined to `path`, rejecting the same names in
+        // both layouts
+        for component in entry_path.components() {
+            if !matches!(component, Component::Normal(_) | Component::CurDir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid entry name `{}`", entry_name),
+                ));
+            }
+        }
 
-    Ok(())
+        if self.flat {
+            return Ok(self.path.join(entry_name.replace(['\\', '/'], "_")));
+        }
+
+        Ok(self.path.join(entry_path))
+    }
+
+    fn create_file_for_entry(&self, entry_name: &str) -> io::Result<File> {
+        let path = self.path_for_entry(entry_name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        File::create(path)
+    }
 }
 
-fn rle_compress_for_save_files<R: Read, W: Write>(
-    r: &mut R,
+fn list<W: Write>(
     w: &mut W,
-    rle_byte: u8,
-) -> std::io::Result<()> {
-    #[derive(Default)]
-    struct State {
-        v: u8,
-        reps: usize,
+    dat_file: &mut DatFile,
+    verbose: bool,
+    sort: Option<SortKey>,
+    desc: bool,
+    filter: Option<&str>,
+) -> Result<(), Error> {
+    let filter = filter.map(|f| f.to_lowercase());
+
+    let mut entries = dat_file
+        .entries
+        .iter()
+        .filter(|e| {
+            filter
+                .as_ref()
+                .is_none_or(|f| e.name.to_lowercase().contains(f))
+        })
+        .map(|e| (e.name.clone(), e.offset, e.size))
+        .collect::<Vec<_>>();
+
+    match sort {
+        Some(SortKey::Name) => entries.sort_by(|a, b| a.0.cmp(&b.0)),
+        Some(SortKey::Offset) => entries.sort_by_key(|e| e.1),
+        Some(SortKey::Size) => entries.sort_by_key(|e| e.2),
+        None => {}
     }
+    if desc {
+        entries.reverse();
+    }
+
+    let total_size: usize = entries.iter().map(|e| e.2).sum();
 
-    impl State {
-        fn new(v: u8) -> State {
-            State { v, reps: 1 }
+    if !verbose {
+        writeln!(w, "+------------------+------------+------------+")?;
+        writeln!(w, "| name             |     offset |       size |")?;
+        writeln!(w, "+------------------+------------+------------+")?;
+        for (name, offset, size) in entries.iter() {
+            writeln!(w, "| {:16} | {:-10} | {:-10} |", name, offset, size)?;
         }
+        writeln!(w, "+------------------+------------+------------+")?;
+        writeln!(w, "{} entries, {} bytes", entries.len(), total_size)?;
+        return Ok(());
     }
 
-    let mut state = State::default();
+    writeln!(
+        w,
+        "+------------------+------------+------------+------------+"
+    )?;
+    writeln!(
+        w,
+        "| name             |     offset |       size |   unpacked |"
+    )?;
+    writeln!(
+        w,
+        "+------------------+------------+------------+------------+"
+    )?;
+    let mut total_unpacked_size = 0;
+    for (name, offset, _) in entries.iter() {
+        let info = dat_file.entry_info(name)?;
+        total_unpacked_size += info.unpacked_size.unwrap_or(info.raw_size);
+        let unpacked_size = match info.unpacked_size {
+            Some(size) if info.compressed => size.to_string(),
+            _ => String::new(),
+        };
+        writeln!(
+            w,
+            "| {:16} | {:-10} | {:-10} | {:>10} |",
+            name, offset, info.raw_size, unpacked_size
+        )?;
+    }
+    writeln!(
+        w,
+        "+------------------+------------+------------+------------+"
+    )?;
+    writeln!(
+        w,
+        "{} entries, {} bytes, {} bytes unpacked",
+        entries.len(),
+        total_size,
+        total_unpacked_size
+    )?;
 
-    let mut output = |state: &mut State| -> std::io::Result<()> {
-        if state.reps > 2 || state.v == 0xf7 {
-            while state.reps > 0 {
-                w.write_u8(rle_byte)?;
-                w.write_u8(state.reps.min(255) as u8)?;
-                w.write_u8(state.v)?;
-                state.reps -= state.reps.min(255);
-            }
-        } else {
-            while state.reps > 0 {
-                w.write_u8(state.v)?;
-                state.reps -= 1;
-            }
-        }
+    Ok(())
+}
 
-        Ok(())
-    };
+/// Returns whether every entry could be read.
+fn verify_dat(dat_file: &mut DatFile, layout: bool) -> bool {
+    let entry_names = dat_file
+        .entry_names()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
 
-    while let Ok(b) = r.read_u8() {
-        if state.reps == 0 {
-            state = State::new(b);
-        } else if state.v == b {
-            state.reps += 1;
-        } else {
-            output(&mut state)?;
-            state = State::new(b);
+    let mut failures = 0;
+    for name in entry_names.iter() {
+        if let Err(e) = dat_file.verify(name) {
+            println!("{}: {}", name, e);
+            failures += 1;
         }
+    }
 
-        if state.v == rle_byte {
-            output(&mut state)?;
+    println!(
+        "{} of {} entries ok",
+        entry_names.len() - failures,
+        entry_names.len()
+    );
+
+    if layout {
+        let report = dat_file.layout_report();
+
+        println!();
+        for e in report.entries.iter() {
+            let note = match e.gap {
+                0 => String::new(),
+                gap if gap < 0 => format!("  overlaps by {} bytes", -gap),
+                gap => format!("  {} byte gap", gap),
+            };
+            println!(
+                "{:16} {:10} {:10} {:10}{}",
+                e.name, e.offset, e.size, e.end, note
+            );
+        }
+        if report.trailing < 0 {
+            println!(
+                "resources extend {} bytes past the end of the file",
+                -report.trailing
+            );
+        } else if report.trailing > 0 {
+            println!("{} bytes after the last resource", report.trailing);
         }
     }
 
-    output(&mut state)
+    failures == 0
 }
 
-fn compress_sav(file_name: &str) -> Result<(), Error> {
-    let mut file = File::open(file_name)?;
+fn decompress_sav(file_name: &str) -> Result<(), Error> {
+    let sav = match SavFile::load(file_name) {
+        Ok(sav) => sav,
+        Err(Error::InvalidSaveFile(_)) => {
+            eprintln!(
+                "`{}` is not a Dune save file - invalid length in header.",
+                file_name
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
 
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
+    let out_file_name: String = file_name
+        .strip_suffix(".SAV")
+        .unwrap_or(file_name)
+        .to_owned()
+        + ".BIN";
 
-    let mut r = Cursor::new(data);
-    let unk0 = r.read_le_u16()?;
-    let rle_word = r.read_le_u16()?;
+    sav.save_decompressed(&out_file_name)?;
 
-    let rle_byte = rle_word as u8;
+    eprintln!("Decompressed `{}` to `{}`", file_name, out_file_name);
 
-    if rle_byte != 0xf7 {
-        println!("`{}` is not a valid decompressed save game.", file_name);
-        return Ok(());
-    }
+    Ok(())
+}
 
-    let mut w = Vec::<u8>::new();
-    rle_compress_for_save_files(&mut r, &mut w, rle_byte)?;
+fn compress_sav(file_name: &str) -> Result<(), Error> {
+    let sav = match SavFile::load_decompressed(file_name) {
+        Ok(sav) => sav,
+        Err(Error::InvalidSaveFile(_)) => {
+            eprintln!("`{}` is not a valid decompressed save game.", file_name);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
 
     let out_file_name: String = file_name
         .strip_suffix(".BIN")
@@ -207,60 +490,201 @@ fn compress_sav(file_name: &str) -> Result<(), Error> {
         .to_owned()
         + ".SAV";
 
-    let mut out_file = BufWriter::new(File::create(&out_file_name)?);
-    out_file.write_le_u16(unk0)?;
-    out_file.write_le_u16(rle_word)?;
-    out_file.write_le_u16((w.len() + 4) as u16)?;
-    out_file.write_all(&w)?;
+    sav.save(&out_file_name)?;
 
-    println!("Compressed `{}` to `{}`", file_name, out_file_name);
+    eprintln!("Compressed `{}` to `{}`", file_name, out_file_name);
 
     Ok(())
 }
 
-fn extract_all(path: &Path, dat_file: &mut DatFile) -> Result<(), Error> {
+type ProgressFn<'a> = &'a mut dyn FnMut(usize, usize, &str);
+
+/// Extracts every resource, calling `progress` with the 1-based index of
+/// the resource, the total number of resources and its name before each.
+fn extract_all(
+    out: &OutDir,
+    dat_file: &mut DatFile,
+    dry_run: bool,
+    manifest: Option<&Path>,
+    recursive: bool,
+    keep_ext: bool,
+    mut progress: Option<ProgressFn>,
+) -> Result<(), Error> {
     let entry_names = dat_file
-        .entries
-        .iter()
-        .map(|e| e.name.clone())
+        .entry_names()
+        .map(str::to_owned)
         .collect::<Vec<_>>();
-    for name in entry_names.iter() {
-        extract(path, dat_file, name)?;
+
+    let mut records = Vec::new();
+
+    for (i, name) in entry_names.iter().enumerate() {
+        if let Some(progress) = progress.as_mut() {
+            progress(i + 1, entry_names.len(), name);
+        }
+        let (out_path, info) = match extract(out, dat_file, name, dry_run, None, keep_ext) {
+            Ok(Some(extracted)) => extracted,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Failed to extract `{}`: {}", name, e);
+                continue;
+            }
+        };
+
+        if manifest.is_some() {
+            records.push(format!(
+                "  {{\"name\": {}, \"path\": {}, \"raw_size\": {}, \"unpacked_size\": {}, \"compressed\": {}}}",
+                json_string(name),
+                json_string(&out_path.to_string_lossy()),
+                info.raw_size,
+                info.unpacked_size.unwrap_or(info.raw_size),
+                info.compressed
+            ));
+        }
+
+        if recursive {
+            if let Err(e) = extract_nested_sprites(out, dat_file, name) {
+                eprintln!("Failed to extract sprites from `{}`: {}", name, e);
+            }
+        }
     }
+
+    if let Some(manifest) = manifest {
+        let mut f = BufWriter::new(File::create(manifest)?);
+        writeln!(f, "[\n{}\n]", records.join(",\n"))?;
+    }
+
     Ok(())
 }
 
-fn extract_raw(path: &Path, dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
+/// Writes the sprites of a resource that looks like a sprite sheet into a
+/// directory named after the extracted resource with `.sprites` appended.
+fn extract_nested_sprites(
+    out: &OutDir,
+    dat_file: &mut DatFile,
+    entry_name: &str,
+) -> Result<(), Error> {
+    let data = dat_file.read(entry_name)?;
+    if !is_sprite_sheet(&data) {
+        return Ok(());
+    }
+
+    let mut out_dir = out
+        .path_for_entry(&extracted_entry_name(entry_name, false))?
+        .into_os_string();
+    out_dir.push(".sprites");
+
+    eprintln!("Extracting sprites from `{}`", entry_name);
+
+    write_sprites(
+        &data,
+        entry_name,
+        Path::new(&out_dir),
+        &SpriteOptions::default(),
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn write_to_stdout(stdout: &mut dyn Write, data: &[u8]) -> io::Result<()> {
+    stdout.write_all(data)?;
+    stdout.flush()
+}
+
+/// Extracts a resource without decompressing, writing it to `stdout`
+/// instead of a file if given.
+fn extract_raw(
+    out: &OutDir,
+    dat_file: &mut DatFile,
+    entry_name: &str,
+    stdout: Option<&mut dyn Write>,
+) -> Result<(), Error> {
     let data = dat_file.read_raw(entry_name)?;
 
-    let mut f = create_file_for_entry(path, entry_name)?;
+    if let Some(stdout) = stdout {
+        write_to_stdout(stdout, data.as_slice())?;
+        return Ok(());
+    }
+
+    let mut f = out.create_file_for_entry(entry_name)?;
     f.write_all(data.as_slice())?;
 
     Ok(())
 }
 
-fn extract(path: &Path, dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
-    println!("Extracting `{}`", entry_name);
-
-    let data = dat_file.read(entry_name).expect("Entry not found");
+/// Returns the file name of a resource without its directory or extension,
+/// or `fallback` if it has none.
+fn entry_stem(entry_name: &str, fallback: &str) -> String {
+    let file_name = entry_name.rsplit('\\').next().unwrap_or(entry_name);
+    Path::new(file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| fallback.into())
+}
 
-    let mut f = if let Some(prefix) = entry_name.strip_suffix(".HSQ") {
-        let new_entry_name = prefix.to_owned() + ".BIN";
-        create_file_for_entry(path, &new_entry_name)?
+/// Returns the name a resource is written under once decompressed.
+fn extracted_entry_name(entry_name: &str, keep_ext: bool) -> String {
+    if keep_ext {
+        entry_name.to_owned()
+    } else if let Some(prefix) = entry_name.strip_suffix(".HSQ") {
+        prefix.to_owned() + ".BIN"
     } else {
-        create_file_for_entry(path, entry_name)?
-    };
+        entry_name.to_owned()
+    }
+}
 
+/// Extracts a resource, returning the path it was written to, or would be
+/// in a dry run, and how it was read. Writes the resource to `stdout`
+/// instead of a file if given, returning `None`.
+fn extract(
+    out: &OutDir,
+    dat_file: &mut DatFile,
+    entry_name: &str,
+    dry_run: bool,
+    stdout: Option<&mut dyn Write>,
+    keep_ext: bool,
+) -> Result<Option<(PathBuf, EntryInfo)>, Error> {
+    if !dry_run {
+        eprintln!("Extracting `{}`", entry_name);
+    }
+
+    let (data, info) = dat_file.read_with_info(entry_name)?;
+
+    if let Some(stdout) = stdout {
+        write_to_stdout(stdout, data.as_slice())?;
+        return Ok(None);
+    }
+
+    let out_entry_name = extracted_entry_name(entry_name, keep_ext);
+    let path = out.path_for_entry(&out_entry_name)?;
+
+    if dry_run {
+        println!("{} ({} bytes)", path.display(), data.len());
+        return Ok(Some((path, info)));
+    }
+
+    let mut f = out.create_file_for_entry(&out_entry_name)?;
     f.write_all(data.as_slice())?;
 
-    Ok(())
+    Ok(Some((path, info)))
 }
 
-fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
-    println!("Extracting sprites from `{}`", entry_name);
-
-    let data = dat_file.read(entry_name)?;
-    let mut r = Cursor::new(data.as_slice());
+/// Reads the table of contents of a sprite sheet, returning its position
+/// and the offsets of the sprites relative to it. Unless `lenient` is set,
+/// the offsets must be strictly increasing.
+fn read_sprite_toc(data: &[u8], lenient: bool) -> Result<(u16, Vec<u16>), Error> {
+    let mut r = Cursor::new(data);
 
     let toc_position = r.read_le_u16()?;
     r.set_position(toc_position as u64);
@@ -269,7 +693,7 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
     let sub_resource_count = first_resource_offset / 2;
 
     if sub_resource_count == 0 || sub_resource_count > 1000 {
-        panic!("Not a sprite sheet");
+        return Err(Error::InvalidSpriteSheet("not a sprite sheet"));
     }
 
     let mut sub_resource_offsets = Vec::with_capacity(sub_resource_count as usize);
@@ -281,14 +705,109 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
 
     for &offset in &sub_resource_offsets {
         if offset as usize >= data.len() {
-            panic!("invalid toc, offset too large");
+            return Err(Error::InvalidSpriteSheet("invalid toc, offset too large"));
         }
     }
 
     // Validate that resource offsets are sequential
     for (a, b) in sub_resource_offsets.iter().tuple_windows() {
-        if a >= b {
-            panic!("invalid toc, non-sequential offsets");
+        if a >= b && !lenient {
+            return Err(Error::InvalidSpriteSheet(
+                "invalid toc, non-sequential offsets",
+            ));
+        }
+    }
+
+    Ok((toc_position, sub_resource_offsets))
+}
+
+fn is_sprite_sheet(data: &[u8]) -> bool {
+    read_sprite_toc(data, false).is_ok()
+}
+
+fn extract_sprites(
+    dat_file: &mut DatFile,
+    entry_name: &str,
+    options: &SpriteOptions,
+) -> Result<(), Error> {
+    eprintln!("Extracting sprites from `{}`", entry_name);
+
+    let data = dat_file.read(entry_name)?;
+    write_sprites(&data, entry_name, Path::new(""), options)
+}
+
+/// A sprite decoded to RGBA.
+struct DecodedSprite {
+    index: usize,
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+/// Writes the sprites of a sprite sheet to `out_dir`, named after the
+/// sheet's resource name.
+fn write_sprites(
+    data: &[u8],
+    entry_name: &str,
+    out_dir: &Path,
+    options: &SpriteOptions,
+) -> Result<(), Error> {
+    let (sprites, pal) = decode_sprites(data, options)?;
+    fs::create_dir_all(out_dir)?;
+
+    let file_stem = entry_stem(entry_name, "SPRITE");
+
+    if options.palette {
+        let mut w = BufWriter::new(File::create(out_dir.join(format!("{}.gpl", file_stem)))?);
+        pal::write_gpl(&mut w, &file_stem, &pal)?;
+    }
+
+    if let Some(gif_path) = &options.gif {
+        let frames = sprites
+            .into_iter()
+            .map(|s| (s.width, s.height, s.data))
+            .collect::<Vec<_>>();
+        return write_gif(gif_path, &frames, &pal, options.delay);
+    }
+
+    if let Some(template) = &options.out_template {
+        if options.index.is_none() && !template.contains("{index}") {
+            return Err(Error::InvalidOutTemplate("missing `{index}`"));
+        }
+    }
+
+    for sprite in sprites {
+        let filename = match &options.out_template {
+            Some(template) => template
+                .replace("{stem}", &file_stem)
+                .replace("{index}", &format!("{:02}", sprite.index))
+                .replace("{width}", &sprite.width.to_string())
+                .replace("{height}", &sprite.height.to_string()),
+            None => format!("{}-{:02}.png", file_stem, sprite.index),
+        };
+        let path = out_dir.join(filename);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_rgba_png(path, sprite.width, sprite.height, &sprite.data)?;
+    }
+
+    Ok(())
+}
+
+/// Decodes the sprites of a sprite sheet selected by `options`, skipping
+/// sprites with implausible dimensions. Also returns the palette they were
+/// drawn with.
+fn decode_sprites(
+    data: &[u8],
+    options: &SpriteOptions,
+) -> Result<(Vec<DecodedSprite>, Vec<u8>), Error> {
+    let (toc_position, sub_resource_offsets) = read_sprite_toc(data, options.lenient)?;
+    let mut r = Cursor::new(data);
+
+    if let Some(index) = options.index {
+        if index >= sub_resource_offsets.len() {
+            return Err(Error::InvalidSpriteIndex(index));
         }
     }
 
@@ -301,48 +820,44 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
     }
 
     if toc_position > 2 {
-        r.set_position(2);
-        loop {
-            let mut v: u16;
-            loop {
-                v = r.read_le_u16().unwrap();
-                if v != 256 {
-                    break;
-                }
-                r.set_position(r.position() + 3);
-            }
-            if v == 0xffff {
-                break;
-            }
-
-            let mut count = (v >> 8) & 0xff;
-            let offset = v & 0xff;
-
-            if count == 0 {
-                count = 256;
-            }
+        // The palette update has to end before the toc
+        let mut pal_r = Cursor::new(&data[..toc_position as usize]);
+        pal_r.set_position(2);
+        pal::apply_palette_update(&mut pal_r, &mut pal)
+            .map_err(|_| Error::InvalidSpriteSheet("invalid palette update"))?;
+    } else if options.require_palette {
+        return Err(Error::MissingPalette);
+    }
 
-            for i in 0..3 * count {
-                pal[(3 * offset + i) as usize] = r.read_u8()?;
-            }
+    // Remap indices by drawing them with the color of their replacement
+    if !options.remap.is_empty() {
+        let original = pal.clone();
+        for &(from, to) in &options.remap {
+            let (from, to) = (from as usize, to as usize);
+            pal[3 * from..3 * from + 3].copy_from_slice(&original[3 * to..3 * to + 3]);
         }
     }
 
-    let file_stem = Path::new(entry_name)
-        .file_stem()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| "SPRITE".into());
+    let mut sprites = Vec::new();
 
     for (i, &offset) in sub_resource_offsets.iter().enumerate() {
-        r.set_position((toc_position as u64) + (offset as u64));
+        if options.index.is_some_and(|index| index != i) {
+            continue;
+        }
 
-        let w0 = r.read_le_u16()?;
-        let w1 = r.read_le_u16()?;
+        if sub_resource_offsets
+            .get(i + 1)
+            .is_some_and(|&next| next <= offset)
+        {
+            eprintln!("Warning: skipping sprite {} with non-increasing offset", i);
+            continue;
+        }
 
-        let flags = ((w0 & 0xff00) >> 8) as u8;
-        let width = (w0 & 0x7fff) as usize;
-        let height = (w1 & 0x00ff) as usize;
-        let pal_offset = ((w1 & 0xff00) >> 8) as u8;
+        r.set_position((toc_position as u64) + (offset as u64));
+
+        let header = SpriteHeader::read(&mut r)?;
+        let (width, height) = (header.width, header.height);
+        let pal_offset = header.pal_offset;
 
         if !(1..=320).contains(&width) || !(1..=200).contains(&height) {
             continue;
@@ -350,18 +865,16 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
 
         let mut image_data = vec![0u8; width * height * 4];
 
-        let filename = format!("{}-{:02}.png", file_stem, i);
-        let path = Path::new(&filename);
-        let file = File::create(path)?;
-        let w = &mut BufWriter::new(file);
-
-        let mut encoder = png::Encoder::new(w, width as u32, height as u32);
-        encoder.set_color(png::ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
-
-        let is_rle_compressed = flags & 0x80 != 0;
+        let is_rle_compressed = header.is_rle_compressed;
+        let is_4bpp = match options.bpp {
+            Some(Bpp::Four) => true,
+            Some(Bpp::Eight) => false,
+            None => header.is_4bpp(),
+        };
+        let transparent = options.transparent(&header, is_4bpp);
 
-        if pal_offset < 254 {
+        if is_4bpp {
+            let pal_offset = options.pal_offset.unwrap_or(pal_offset);
             if !is_rle_compressed {
                 sprite::draw_4bpp(
                     image_data.as_mut_slice(),
@@ -370,6 +883,7 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
                     height,
                     &pal,
                     pal_offset,
+                    transparent,
                 )?;
             } else {
                 sprite::draw_4bpp_rle(
@@ -379,6 +893,7 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
                     height,
                     &pal,
                     pal_offset,
+                    transparent,
                 )?;
             }
         } else if !is_rle_compressed {
@@ -388,7 +903,7 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
                 width,
                 height,
                 &pal,
-                pal_offset,
+                transparent,
             )?;
         } else {
             sprite::draw_8bpp_rle(
@@ -397,77 +912,146 @@ fn extract_sprites(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error
                 width,
                 height,
                 &pal,
-                pal_offset,
+                transparent,
             )?;
         }
 
-        let mut writer = encoder.write_header()?;
-        writer.write_image_data(&image_data)?;
-    }
-    Ok(())
-}
+        if options.flip_x {
+            sprite::flip_x(image_data.as_mut_slice(), width, height);
+        }
+        if options.flip_y {
+            sprite::flip_y(image_data.as_mut_slice(), width, height);
+        }
 
-fn extract_font(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
-    let cw = 8;
-    let ch1 = 9;
-    let ch2 = 7;
-    let width = cw * 16;
-    let height = ch1 * 8 + ch2 * 8;
+        sprites.push(DecodedSprite {
+            index: i,
+            width,
+            height,
+            data: image_data,
+        });
+    }
 
-    let mut image_data = vec![0u8; width * height * 4];
+    Ok((sprites, pal))
+}
 
+fn sprite_info<W: Write>(
+    w: &mut W,
+    dat_file: &mut DatFile,
+    entry_name: &str,
+    json: bool,
+) -> Result<(), Error> {
     let data = dat_file.read(entry_name)?;
+    let (toc_position, sub_resource_offsets) = read_sprite_toc(&data, false)?;
     let mut r = Cursor::new(data.as_slice());
 
-    let mut ws = [0; 256];
-    for w in &mut ws {
-        *w = r.read_u8()? as i32;
+    let mut records = Vec::new();
+
+    if !json {
+        writeln!(w, "index width height bpp pal_offset rle")?;
     }
 
-    for al in 0..128 {
-        r.set_position((0x100 + ch1 * al) as u64);
+    for (i, &offset) in sub_resource_offsets.iter().enumerate() {
+        r.set_position((toc_position as u64) + (offset as u64));
+        let header = SpriteHeader::read(&mut r)?;
+        let bpp = if header.is_4bpp() { 4 } else { 8 };
+
+        if json {
+            records.push(format!(
+                "  {{\"index\": {}, \"width\": {}, \"height\": {}, \"bpp\": {}, \"pal_offset\": {}, \"rle\": {}}}",
+                i, header.width, header.height, bpp, header.pal_offset, header.is_rle_compressed
+            ));
+        } else {
+            writeln!(
+                w,
+                "{:5} {:5} {:6} {:3} {:10} {}",
+                i, header.width, header.height, bpp, header.pal_offset, header.is_rle_compressed
+            )?;
+        }
+    }
 
-        let x = cw * (al % 16);
-        let y = ch1 * (al / 16);
+    if json {
+        writeln!(w, "[\n{}\n]", records.join(",\n"))?;
+    }
 
-        for dy in 0..ch1 {
-            let bs = r.read_u8()?;
-            for dx in 0..cw {
-                if (bs << dx) & 0x80 == 0x80 {
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 0] = 255;
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 1] = 255;
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 2] = 255;
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 3] = 255;
-                }
+    Ok(())
+}
+
+/// Writes RGBA images drawn with the 6-bit palette `pal` as the frames of a
+/// looping animated GIF, using the palette as its global color table. Each
+/// frame is padded to the size of the largest one. Transparent pixels and
+/// the padding use a palette index whose color no frame uses, falling back
+/// to a quantized palette per frame if every color is in use.
+fn write_gif(
+    path: &Path,
+    frames: &[(usize, usize, Vec<u8>)],
+    pal: &[u8],
+    delay: u16,
+) -> Result<(), Error> {
+    let width = frames.iter().map(|f| f.0).max().unwrap_or(1);
+    let height = frames.iter().map(|f| f.1).max().unwrap_or(1);
+
+    let global_palette = pal
+        .iter()
+        .map(|&c| pal::scale_6bit_to_8bit(c))
+        .collect::<Vec<_>>();
+
+    let mut indices = HashMap::new();
+    for (i, rgb) in global_palette.chunks_exact(3).enumerate() {
+        indices.entry([rgb[0], rgb[1], rgb[2]]).or_insert(i as u8);
+    }
+
+    let mut used = [false; 256];
+    for (_, _, image_data) in frames {
+        for p in image_data.chunks_exact(4).filter(|p| p[3] != 0) {
+            if let Some(&i) = indices.get(&[p[0], p[1], p[2]]) {
+                used[i as usize] = true;
             }
         }
     }
-    for al in 0..128 {
-        r.set_position((0x100 + 0x480 + ch2 * al) as u64);
+    let transparent = used.iter().position(|&u| !u).map(|i| i as u8);
 
-        let x = cw * (al % 16);
-        let y = ch2 * (al / 16) + (ch1 * 8);
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &global_palette)?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
 
-        for dy in 0..ch2 {
-            let bs = r.read_u8()?;
-            for dx in 0..cw {
-                if (bs << dx) & 0x80 == 0x80 {
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 0] = 255;
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 1] = 255;
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 2] = 255;
-                    image_data[4 * ((y + dy) * width + (x + dx)) + 3] = 255;
-                }
-            }
+    for (w, h, image_data) in frames {
+        let mut padded = vec![0u8; width * height * 4];
+        for y in 0..*h {
+            padded[4 * y * width..4 * (y * width + w)]
+                .copy_from_slice(&image_data[4 * y * w..4 * (y + 1) * w]);
         }
+
+        let mut frame = match transparent {
+            Some(transparent) => gif::Frame {
+                width: width as u16,
+                height: height as u16,
+                buffer: padded
+                    .chunks_exact(4)
+                    .map(|p| match p[3] {
+                        0 => transparent,
+                        _ => *indices.get(&[p[0], p[1], p[2]]).unwrap_or(&transparent),
+                    })
+                    .collect::<Vec<_>>()
+                    .into(),
+                transparent: Some(transparent),
+                ..gif::Frame::default()
+            },
+            None => gif::Frame::from_rgba_speed(width as u16, height as u16, &mut padded, 10),
+        };
+        frame.delay = delay;
+        frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&frame)?;
     }
 
-    let file_stem = Path::new(entry_name)
-        .file_stem()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap();
+    Ok(())
+}
 
-    let filename = format!("{}.png", file_stem);
-    let path = Path::new(&filename);
+fn write_rgba_png<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    image_data: &[u8],
+) -> Result<(), Error> {
     let file = File::create(path)?;
     let w = &mut BufWriter::new(file);
 
@@ -476,42 +1060,1025 @@ fn extract_font(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
     encoder.set_depth(png::BitDepth::Eight);
 
     let mut writer = encoder.write_header()?;
-    writer.write_image_data(&image_data)?;
+    writer.write_image_data(image_data)?;
+
+    Ok(())
+}
+
+fn extract_font(
+    dat_file: &mut DatFile,
+    entry_name: &str,
+    layout: &FontLayout,
+    widths: bool,
+) -> Result<(), Error> {
+    let data = dat_file.read(entry_name)?;
+    write_font(&data, entry_name, Path::new(""), layout, widths)
+}
+
+/// Writes the glyphs of a font resource to `out_dir`, named after the
+/// resource name, and its glyph widths if `widths` is set.
+fn write_font(
+    data: &[u8],
+    entry_name: &str,
+    out_dir: &Path,
+    layout: &FontLayout,
+    widths: bool,
+) -> Result<(), Error> {
+    let font = Font::new(data, layout)?;
+
+    let cw = font::GLYPH_WIDTH;
+    let ch1 = font.banks[0].height;
+    let ch2 = font.banks[1].height;
+    let width = cw * 16;
+    let height = ch1 * 8 + ch2 * 8;
+
+    let mut image_data = vec![0u8; width * height * 4];
+
+    for al in 0..128 {
+        let x = cw * (al % 16);
+        let y = ch1 * (al / 16);
+
+        font.banks[0].draw_glyph(&mut image_data, width, x, y, al);
+    }
+    for al in 0..128 {
+        let x = cw * (al % 16);
+        let y = ch2 * (al / 16) + (ch1 * 8);
+
+        font.banks[1].draw_glyph(&mut image_data, width, x, y, al);
+    }
+
+    let file_stem = entry_stem(entry_name, "FONT");
+
+    write_rgba_png(
+        out_dir.join(format!("{}.png", file_stem)),
+        width,
+        height,
+        &image_data,
+    )?;
+
+    println!("Glyph widths:\n{:?}", font.widths);
+
+    if widths {
+        let filename = out_dir.join(format!("{}.widths.json", file_stem));
+        let mut file = BufWriter::new(File::create(filename)?);
+        writeln!(file, "[{}]", font.widths.iter().join(", "))?;
+    }
+
+    Ok(())
+}
+
+fn dump_hsq_stats(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
+    let Some(stats) = dat_file.hsq_stats(entry_name)? else {
+        println!("`{}` is not compressed", entry_name);
+        return Ok(());
+    };
+
+    let matches = stats.short_matches + stats.long_matches;
+
+    println!("literals:      {:8}", stats.literals);
+    println!("short matches: {:8}", stats.short_matches);
+    println!("long matches:  {:8}", stats.long_matches);
+    if stats.literals + matches > 0 {
+        println!(
+            "literal ratio: {:8.3}",
+            stats.literals as f64 / (stats.literals + matches) as f64
+        );
+    }
+
+    println!("\nmatch lengths:");
+    for (length, n) in stats.match_lengths.iter() {
+        println!("  {:5}: {:8}", length, n);
+    }
+
+    println!("\nmatch offsets:");
+    for (offset, n) in stats.match_offsets.iter() {
+        println!("  <= {:5}: {:8}", offset, n);
+    }
+
+    Ok(())
+}
+
+fn extract_sound(dat_file: &mut DatFile, entry_name: &str, sample_rate: u32) -> Result<(), Error> {
+    eprintln!("Extracting sound from `{}`", entry_name);
+
+    let data = dat_file.read(entry_name)?;
+
+    let sound = if voc::is_voc(&data) {
+        voc::decode_voc(&data)?
+    } else {
+        Sound {
+            sample_rate,
+            samples: data,
+        }
+    };
 
-    println!("Glyph widths:\n{:?}", ws);
+    let file_stem = entry_stem(entry_name, "SOUND");
+
+    let filename = format!("{}.wav", file_stem);
+    let mut w = BufWriter::new(File::create(filename)?);
+    voc::write_wav(&mut w, &sound)?;
 
     Ok(())
 }
 
+/// Guesses the type of a resource and extracts it with the matching
+/// command.
+fn render(dat_file: &mut DatFile, entry_name: &str) -> Result<(), Error> {
+    let data = dat_file.read(entry_name)?;
+    let font_layout = FontLayout::default();
+
+    if is_sprite_sheet(&data) {
+        extract_sprites(dat_file, entry_name, &SpriteOptions::default())
+    } else if voc::is_voc(&data) {
+        extract_sound(dat_file, entry_name, 11025)
+    } else if data.len() == font_layout.bank2_offset + 128 * font_layout.bank2_height {
+        extract_font(dat_file, entry_name, &font_layout, false)
+    } else {
+        for (i, line) in data.chunks(16).enumerate() {
+            println!("{:08x}: {:02x}", i * 16, line.iter().format(" "));
+        }
+        Ok(())
+    }
+}
+
+fn render_text(dat_file: &mut DatFile, font_entry: &str, text: &str) -> Result<(), Error> {
+    let data = dat_file.read(font_entry)?;
+    let font = Font::new(&data, &FontLayout::default())?;
+
+    let lines = text.lines().map(|l| l.as_bytes()).collect::<Vec<_>>();
+    let line_height = font.banks[0].height;
+
+    let width = lines
+        .iter()
+        .map(|l| l.iter().map(|&c| font.widths[c as usize] as usize).sum())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let height = (line_height * lines.len()).max(1);
+
+    let mut image_data = vec![0u8; width * height * 4];
+
+    for (i, line) in lines.iter().enumerate() {
+        let mut x = 0;
+        for &c in line.iter() {
+            let (bank, glyph) = font.glyph(c);
+            bank.draw_glyph(&mut image_data, width, x, line_height * i, glyph);
+            x += font.widths[c as usize] as usize;
+        }
+    }
+
+    let file_stem = entry_stem(font_entry, "FONT");
+
+    write_rgba_png(
+        format!("{}-text.png", file_stem),
+        width,
+        height,
+        &image_data,
+    )
+}
+
+/// Opens DUNE.DAT for the commands that read from it, exiting with an
+/// error message if it can't be opened.
+fn open_dat_file(cli: &Cli) -> DatFile {
+    let mut dat_file = match DatFile::open(&cli.dat_path, &cli.dat_variant.record_layout()) {
+        Ok(dat_file) => dat_file,
+        Err(e) => {
+            eprintln!("Failed to open DUNE.DAT: {}", e);
+            process::exit(1);
+        }
+    };
+    dat_file.ignore_hsq_checksum = cli.ignore_hsq_checksum;
+    dat_file
+}
+
 fn main() -> Result<(), Error> {
     let cli = Cli::parse();
 
-    let out_path = cli.out_path;
-    let mut dat_file = DatFile::open(&cli.dat_path).expect("Failed to open DUNE.DAT");
+    let out = OutDir {
+        path: cli.out_path.clone(),
+        flat: cli.flat,
+    };
 
     match &cli.command {
-        Commands::List => list(&mut dat_file),
+        Commands::List {
+            verbose,
+            sort,
+            desc,
+            filter,
+        } => {
+            list(
+                &mut io::stdout().lock(),
+                &mut open_dat_file(&cli),
+                *verbose,
+                *sort,
+                *desc,
+                filter.as_deref(),
+            )?;
+        }
+        Commands::VerifyDat { layout } => {
+            if !verify_dat(&mut open_dat_file(&cli), *layout) {
+                process::exit(1);
+            }
+        }
         Commands::DecompressSav { file_name } => {
             decompress_sav(file_name)?;
         }
         Commands::CompressSav { file_name } => {
             compress_sav(file_name)?;
         }
-        Commands::ExtractAll => {
-            extract_all(&out_path, &mut dat_file)?;
+        Commands::ExtractAll {
+            dry_run,
+            manifest,
+            recursive,
+            keep_ext,
+        } => {
+            let mut progress = |current: usize, total: usize, _: &str| {
+                eprint!("[{:3}%] ", 100 * current / total);
+            };
+            let progress: Option<ProgressFn> = if *dry_run { None } else { Some(&mut progress) };
+            extract_all(
+                &out,
+                &mut open_dat_file(&cli),
+                *dry_run,
+                manifest.as_deref(),
+                *recursive,
+                *keep_ext,
+                progress,
+            )?;
+        }
+        Commands::ExtractRaw { entry_name, stdout } => {
+            let mut w = io::stdout().lock();
+            let stdout = stdout.then_some(&mut w as &mut dyn Write);
+            extract_raw(&out, &mut open_dat_file(&cli), entry_name, stdout)?;
+        }
+        Commands::Extract {
+            entry_name,
+            dry_run,
+            stdout,
+            keep_ext,
+        } => {
+            let mut w = io::stdout().lock();
+            let stdout = stdout.then_some(&mut w as &mut dyn Write);
+            extract(
+                &out,
+                &mut open_dat_file(&cli),
+                entry_name,
+                *dry_run,
+                stdout,
+                *keep_ext,
+            )?;
+        }
+        Commands::ExtractSprites {
+            entry_name,
+            options,
+        } => {
+            extract_sprites(&mut open_dat_file(&cli), entry_name, options)?;
+        }
+        Commands::SpriteInfo { entry_name, json } => {
+            sprite_info(
+                &mut io::stdout().lock(),
+                &mut open_dat_file(&cli),
+                entry_name,
+                *json,
+            )?;
         }
-        Commands::ExtractRaw { entry_name } => {
-            extract_raw(&out_path, &mut dat_file, entry_name)?;
+        Commands::ExtractFont {
+            entry_name,
+            widths,
+            bank1_height,
+            bank2_height,
+            bank2_offset,
+        } => {
+            let layout = FontLayout {
+                bank1_height: *bank1_height,
+                bank2_height: *bank2_height,
+                bank2_offset: *bank2_offset,
+            };
+            extract_font(&mut open_dat_file(&cli), entry_name, &layout, *widths)?;
         }
-        Commands::Extract { entry_name } => {
-            extract(&out_path, &mut dat_file, entry_name)?;
+        Commands::DumpHsqStats { entry_name } => {
+            dump_hsq_stats(&mut open_dat_file(&cli), entry_name)?;
         }
-        Commands::ExtractSprites { entry_name } => {
-            extract_sprites(&mut dat_file, entry_name)?;
+        Commands::ExtractSound {
+            entry_name,
+            sample_rate,
+        } => {
+            extract_sound(&mut open_dat_file(&cli), entry_name, *sample_rate)?;
         }
-        Commands::ExtractFont { entry_name } => {
-            extract_font(&mut dat_file, entry_name)?;
+        Commands::RenderText { font_entry, text } => {
+            render_text(&mut open_dat_file(&cli), font_entry, text)?;
+        }
+        Commands::Render { entry_name } => {
+            render(&mut open_dat_file(&cli), entry_name)?;
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dat_file::tests::dat_file,
+        unhsq::tests::{hsq, truncate_hsq},
+    };
+
+    fn out_dir(flat: bool) -> OutDir {
+        OutDir {
+            path: PathBuf::from("dump"),
+            flat,
+        }
+    }
+
+    /// Returns an empty output directory under the system temp directory.
+    fn temp_out_dir(name: &str) -> OutDir {
+        let path = std::env::temp_dir().join(format!("dune-extract-test-{}", name));
+        _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        OutDir { path, flat: false }
+    }
+
+    /// A 4x2 4bpp sprite holding indices 1, 2, 3, 4 and 0, 0, 5, 1.
+    const SPRITE: &[u8] = &[4, 0, 2, 0, 0x21, 0x43, 0x00, 0x15];
+
+    /// Builds a sheet with no palette holding `sprites`, each a header
+    /// followed by its pixel data.
+    fn sheet_of(sprites: &[&[u8]]) -> Vec<u8> {
+        let mut toc = Vec::new();
+        let mut data = Vec::new();
+        for sprite in sprites {
+            toc.extend_from_slice(&((2 * sprites.len() + data.len()) as u16).to_le_bytes());
+            data.extend_from_slice(sprite);
+        }
+
+        let mut sheet = vec![2, 0];
+        sheet.extend_from_slice(&toc);
+        sheet.extend_from_slice(&data);
+        sheet
+    }
+
+    /// A sheet holding `SPRITE`.
+    fn sprite_sheet() -> Vec<u8> {
+        sheet_of(&[SPRITE])
+    }
+
+    #[test]
+    fn path_for_entry_nests_backslash_names() {
+        let path = out_dir(false).path_for_entry("SUB\\FILE.BIN").unwrap();
+        assert_eq!(path, Path::new("dump/SUB/FILE.BIN"));
+    }
+
+    #[test]
+    fn path_for_entry_flattens_backslash_names() {
+        let path = out_dir(true).path_for_entry("SUB\\FILE").unwrap();
+        assert_eq!(path, Path::new("dump/SUB_FILE"));
+        let path = out_dir(false).path_for_entry("SUB\\FILE").unwrap();
+        assert_eq!(path, Path::new("dump/SUB/FILE"));
+    }
+
+    #[test]
+    fn path_for_entry_rejects_parent_components() {
+        let err = out_dir(false).path_for_entry("..\\..\\evil").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn path_for_entry_stays_in_output_directory() {
+        for flat in [false, true] {
+            for name in ["..\\..\\evil", "SUB\\..\\..\\evil", "/evil"] {
+                let err = out_dir(flat).path_for_entry(name).unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", name);
+            }
+        }
+
+        let path = out_dir(false).path_for_entry("\\evil").unwrap();
+        assert_eq!(path, Path::new("dump/evil"));
+        let path = out_dir(true).path_for_entry("\\evil").unwrap();
+        assert_eq!(path, Path::new("dump/_evil"));
+    }
+
+    #[test]
+    fn path_for_entry_rejects_absolute_names() {
+        let err = out_dir(false).path_for_entry("/evil").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn decodes_sprite_sheet_from_dat() {
+        let mut dat_file = dat_file(&[("SHEET.BIN", &sprite_sheet())]);
+        let data = dat_file.read("SHEET.BIN").unwrap();
+
+        assert!(is_sprite_sheet(&data));
+        let (sprites, _) = decode_sprites(&data, &SpriteOptions::default()).unwrap();
+        assert_eq!(sprites.len(), 1);
+        assert_eq!((sprites[0].width, sprites[0].height), (4, 2));
+        assert_eq!(sprites[0].data.len(), 4 * 2 * 4);
+    }
+
+    #[test]
+    fn index_option_writes_one_sprite() {
+        let sheet = sheet_of(&[SPRITE, SPRITE, SPRITE]);
+        let out = temp_out_dir("index");
+        let options = SpriteOptions {
+            index: Some(2),
+            ..SpriteOptions::default()
+        };
+
+        write_sprites(&sheet, "SHEET.BIN", &out.path, &options).unwrap();
+
+        let files = fs::read_dir(&out.path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["SHEET-02.png"]);
+
+        let options = SpriteOptions {
+            index: Some(3),
+            ..SpriteOptions::default()
+        };
+        assert!(matches!(
+            write_sprites(&sheet, "SHEET.BIN", &out.path, &options),
+            Err(Error::InvalidSpriteIndex(3))
+        ));
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn pal_offset_option_shifts_indices() {
+        let sheet = sprite_sheet();
+        let red = |options: &SpriteOptions| {
+            let (sprites, _) = decode_sprites(&sheet, options).unwrap();
+            sprites[0].data.chunks(4).map(|p| p[0]).collect::<Vec<_>>()
+        };
+        let gray = |i: &[u8]| {
+            i.iter()
+                .map(|&i| pal::scale_6bit_to_8bit(i))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(red(&SpriteOptions::default())[..4], gray(&[1, 2, 3, 4]));
+        let options = SpriteOptions {
+            pal_offset: Some(16),
+            ..SpriteOptions::default()
+        };
+        assert_eq!(red(&options)[..4], gray(&[17, 18, 19, 20]));
+        let options = SpriteOptions {
+            pal_offset: Some(16),
+            flip_x: true,
+            ..SpriteOptions::default()
+        };
+        assert_eq!(red(&options)[..4], gray(&[20, 19, 18, 17]));
+    }
+
+    #[test]
+    fn require_palette_rejects_palette_less_sheet() {
+        let sheet = sprite_sheet();
+
+        let (sprites, _) = decode_sprites(&sheet, &SpriteOptions::default()).unwrap();
+        let gray = pal::scale_6bit_to_8bit(1);
+        assert_eq!(sprites[0].data[..4], [gray, gray, gray, 255]);
+
+        let options = SpriteOptions {
+            require_palette: true,
+            ..SpriteOptions::default()
+        };
+        assert!(matches!(
+            decode_sprites(&sheet, &options),
+            Err(Error::MissingPalette)
+        ));
+    }
+
+    #[test]
+    fn sprite_info_matches_decoded_sprites() {
+        let sheet = sheet_of(&[SPRITE, &[2, 0, 1, 0, 0x21, 0x43]]);
+        let mut dat_file = dat_file(&[("SHEET.BIN", &sheet)]);
+
+        let mut out = Vec::new();
+        sprite_info(&mut out, &mut dat_file, "SHEET.BIN", true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let (sprites, _) = decode_sprites(&sheet, &SpriteOptions::default()).unwrap();
+        let records = out
+            .lines()
+            .filter(|l| l.starts_with("  {"))
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), sprites.len());
+        for (record, sprite) in records.iter().zip(sprites.iter()) {
+            assert!(record.contains(&format!(
+                "\"index\": {}, \"width\": {}, \"height\": {}, \"bpp\": 4",
+                sprite.index, sprite.width, sprite.height
+            )));
+        }
+    }
+
+    #[test]
+    fn remap_option_swaps_colors() {
+        let sheet = sprite_sheet();
+        let options = SpriteOptions {
+            remap: vec![(1, 2), (2, 1)],
+            ..SpriteOptions::default()
+        };
+
+        let (sprites, _) = decode_sprites(&sheet, &options).unwrap();
+        let red = sprites[0].data.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+        let gray = [2, 1, 3, 4].map(pal::scale_6bit_to_8bit);
+        assert_eq!(red[..4], gray);
+    }
+
+    #[test]
+    fn lenient_option_skips_empty_sprites() {
+        // Sprite 0 shares its offset with sprite 1 and has no data
+        let mut sheet = vec![2, 0, 6, 0, 6, 0, 14, 0];
+        sheet.extend_from_slice(SPRITE);
+        sheet.extend_from_slice(SPRITE);
+
+        assert!(matches!(
+            decode_sprites(&sheet, &SpriteOptions::default()),
+            Err(Error::InvalidSpriteSheet(_))
+        ));
+
+        let options = SpriteOptions {
+            lenient: true,
+            ..SpriteOptions::default()
+        };
+        let (sprites, _) = decode_sprites(&sheet, &options).unwrap();
+        assert_eq!(sprites.iter().map(|s| s.index).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn out_template_option_names_files() {
+        let sheet = sheet_of(&[SPRITE, SPRITE]);
+        let out = temp_out_dir("out-template");
+        let options = SpriteOptions {
+            out_template: Some("frames/{stem}_{index}.png".into()),
+            ..SpriteOptions::default()
+        };
+
+        write_sprites(&sheet, "SHEET.BIN", &out.path, &options).unwrap();
+
+        assert!(out.path.join("frames/SHEET_00.png").is_file());
+        assert!(out.path.join("frames/SHEET_01.png").is_file());
+        assert_eq!(fs::read_dir(out.path.join("frames")).unwrap().count(), 2);
+
+        let options = SpriteOptions {
+            out_template: Some("{stem}.png".into()),
+            ..SpriteOptions::default()
+        };
+        assert!(matches!(
+            write_sprites(&sheet, "SHEET.BIN", &out.path, &options),
+            Err(Error::InvalidOutTemplate(_))
+        ));
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn palette_option_writes_sheet_palette() {
+        // Colors 1 and 2 set by the palette update, then a toc at 12
+        let mut sheet = vec![12, 0, 0x01, 0x02, 10, 20, 30, 40, 50, 60, 0xff, 0xff];
+        sheet.extend_from_slice(&[2, 0]);
+        sheet.extend_from_slice(SPRITE);
+        let out = temp_out_dir("palette");
+        let options = SpriteOptions {
+            palette: true,
+            ..SpriteOptions::default()
+        };
+
+        write_sprites(&sheet, "SHEET.BIN", &out.path, &options).unwrap();
+
+        let (_, pal) = decode_sprites(&sheet, &options).unwrap();
+        assert_eq!(pal[3..9], [10, 20, 30, 40, 50, 60]);
+
+        let gpl = fs::read_to_string(out.path.join("SHEET.gpl")).unwrap();
+        let colors = gpl
+            .lines()
+            .skip(4)
+            .map(|l| {
+                l.split_whitespace()
+                    .take(3)
+                    .map(|c| c.parse::<u8>().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(colors.len(), 256);
+        for (color, rgb) in colors.iter().zip(pal.chunks(3)) {
+            assert_eq!(
+                color[..],
+                rgb.iter()
+                    .map(|&c| pal::scale_6bit_to_8bit(c))
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn transparent_option_skips_index() {
+        // The sprite holds indices 1, 2, 3, 4 and 0, 0, 5, 1
+        let sheet = sprite_sheet();
+
+        let alpha = |options: &SpriteOptions| {
+            let (sprites, _) = decode_sprites(&sheet, options).unwrap();
+            sprites[0].data.chunks(4).map(|p| p[3]).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            alpha(&SpriteOptions::default()),
+            [255, 255, 255, 255, 0, 0, 255, 255]
+        );
+        let options = SpriteOptions {
+            transparent: Some(5),
+            ..SpriteOptions::default()
+        };
+        assert_eq!(alpha(&options), [255, 255, 255, 255, 255, 255, 0, 255]);
+        let options = SpriteOptions {
+            opaque: true,
+            ..SpriteOptions::default()
+        };
+        assert_eq!(alpha(&options), [255; 8]);
+    }
+
+    #[test]
+    fn extract_all_recursive_writes_sprites_and_skips_bad_sheets() {
+        // A 3x1 4bpp RLE sprite whose only run is 128 bytes long
+        let mut bad_sheet = vec![2, 0, 2, 0, 0x03, 0x80, 0x01, 0x00, 0x7f];
+        bad_sheet.extend_from_slice(&[0; 128]);
+        let sheet = sprite_sheet();
+
+        let mut dat_file = dat_file(&[
+            ("BAD.BIN", &bad_sheet),
+            ("SHEET.BIN", &sheet),
+            ("LAST.BIN", b"last"),
+        ]);
+        let out = temp_out_dir("recursive");
+
+        extract_all(&out, &mut dat_file, false, None, true, false, None).unwrap();
+
+        assert_eq!(fs::read(out.path.join("SHEET.BIN")).unwrap(), sheet);
+        assert!(out.path.join("SHEET.BIN.sprites/SHEET-00.png").is_file());
+        assert!(out.path.join("BAD.BIN").is_file());
+        assert!(!out.path.join("BAD.BIN.sprites").exists());
+        assert!(out.path.join("LAST.BIN").is_file());
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn bpp_option_overrides_the_guess() {
+        // One 2x1 4bpp sprite
+        let sheet = [2, 0, 2, 0, 2, 0, 1, 0, 0x21, 0x43];
+
+        let decode = |bpp| {
+            let options = SpriteOptions {
+                bpp,
+                ..SpriteOptions::default()
+            };
+            decode_sprites(&sheet, &options).unwrap().0.remove(0).data
+        };
+
+        assert_eq!(decode(None), decode(Some(Bpp::Four)));
+        assert_ne!(decode(None), decode(Some(Bpp::Eight)));
+        // The first byte read as one pixel of the grayscale palette
+        let gray = pal::scale_6bit_to_8bit(0x21);
+        assert_eq!(&decode(Some(Bpp::Eight))[..4], [gray, gray, gray, 255]);
+    }
+
+    #[test]
+    fn forcing_4bpp_on_8bpp_rle_sprite_fails_cleanly() {
+        // One 3x1 8bpp RLE sprite holding a literal run of three pixels
+        let sheet = [2, 0, 2, 0, 0x03, 0x80, 0x01, 0xfe, 0x02, 10, 20, 30];
+
+        assert!(decode_sprites(&sheet, &SpriteOptions::default()).is_ok());
+        let options = SpriteOptions {
+            bpp: Some(Bpp::Four),
+            ..SpriteOptions::default()
+        };
+        assert!(matches!(
+            decode_sprites(&sheet, &options),
+            Err(Error::IOError(e)) if e.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn dry_run_creates_no_files() {
+        let packed = hsq(b"hello");
+        let mut dat_file = dat_file(&[("A.HSQ", &packed), ("SUB\\B.BIN", b"b")]);
+        let out = temp_out_dir("dry-run");
+
+        extract_all(&out, &mut dat_file, true, None, false, false, None).unwrap();
+        let (path, _) = extract(&out, &mut dat_file, "A.HSQ", true, None, false)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(path, out.path.join("A.BIN"));
+        assert_eq!(fs::read_dir(&out.path).unwrap().count(), 0);
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn stdout_option_writes_resource_instead_of_file() {
+        let packed = hsq(b"hello");
+        let mut dat_file = dat_file(&[("A.HSQ", &packed)]);
+        let out = temp_out_dir("stdout");
+
+        let mut stdout = Vec::new();
+        let extracted = extract(
+            &out,
+            &mut dat_file,
+            "A.HSQ",
+            false,
+            Some(&mut stdout),
+            false,
+        );
+        assert!(extracted.unwrap().is_none());
+        assert_eq!(stdout, b"hello");
+
+        let mut stdout = Vec::new();
+        extract_raw(&out, &mut dat_file, "A.HSQ", Some(&mut stdout)).unwrap();
+        assert_eq!(stdout, packed);
+
+        assert_eq!(fs::read_dir(&out.path).unwrap().count(), 0);
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    /// Returns the `(name, size)` of the rows of a `list` table.
+    fn list_rows(output: &str) -> Vec<(String, usize)> {
+        output
+            .lines()
+            .filter(|l| l.starts_with("| ") && !l.starts_with("| name"))
+            .map(|l| {
+                let columns = l.split('|').map(str::trim).collect::<Vec<_>>();
+                (columns[1].to_owned(), columns[3].parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn list_sorts_and_filters_entries() {
+        let mut dat_file = dat_file(&[
+            ("B.BIN", b"bb"),
+            ("A.BIN", b"aaaa"),
+            ("C.HSQ", b"c"),
+            ("D.BIN", b"ddd"),
+        ]);
+
+        let mut out = Vec::new();
+        list(
+            &mut out,
+            &mut dat_file,
+            false,
+            Some(SortKey::Size),
+            true,
+            None,
+        )
+        .unwrap();
+        let sizes = list_rows(&String::from_utf8(out).unwrap())
+            .into_iter()
+            .map(|(_, size)| size)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [4, 3, 2, 1]);
+
+        let mut out = Vec::new();
+        list(
+            &mut out,
+            &mut dat_file,
+            false,
+            Some(SortKey::Name),
+            false,
+            Some("bin"),
+        )
+        .unwrap();
+        let names = list_rows(&String::from_utf8(out).unwrap())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["A.BIN", "B.BIN", "D.BIN"]);
+    }
+
+    #[test]
+    fn list_prints_totals() {
+        let packed = hsq(b"hello world");
+        let mut dat_file = dat_file(&[("A.HSQ", &packed), ("B.BIN", b"bb")]);
+
+        let mut out = Vec::new();
+        list(&mut out, &mut dat_file, false, None, false, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.lines().last(),
+            Some(format!("2 entries, {} bytes", packed.len() + 2).as_str())
+        );
+
+        let mut out = Vec::new();
+        list(&mut out, &mut dat_file, true, None, false, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.lines().last(),
+            Some(format!("2 entries, {} bytes, 13 bytes unpacked", packed.len() + 2).as_str())
+        );
+    }
+
+    #[test]
+    fn progress_is_reported_once_per_entry() {
+        let mut dat_file = dat_file(&[("A.BIN", b"a"), ("B.BIN", b"b"), ("C.BIN", b"c")]);
+        let out = temp_out_dir("progress");
+
+        let mut calls = Vec::new();
+        let mut progress = |current: usize, total: usize, name: &str| {
+            calls.push((current, total, name.to_owned()));
+        };
+        extract_all(
+            &out,
+            &mut dat_file,
+            true,
+            None,
+            false,
+            false,
+            Some(&mut progress),
+        )
+        .unwrap();
+
+        assert_eq!(
+            calls,
+            [
+                (1, 3, "A.BIN".to_owned()),
+                (2, 3, "B.BIN".to_owned()),
+                (3, 3, "C.BIN".to_owned())
+            ]
+        );
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn keep_ext_option_keeps_hsq_name() {
+        let packed = hsq(b"hello");
+        let mut dat_file = dat_file(&[("A.HSQ", &packed)]);
+        let out = temp_out_dir("keep-ext");
+
+        let (path, _) = extract(&out, &mut dat_file, "A.HSQ", false, None, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, out.path.join("A.HSQ"));
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+        assert!(!out.path.join("A.BIN").exists());
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn extract_reports_missing_entry() {
+        let mut dat_file = dat_file(&[("A.BIN", b"a")]);
+        let out = temp_out_dir("missing");
+
+        assert!(matches!(
+            extract(&out, &mut dat_file, "B.BIN", false, None, false),
+            Err(Error::EntryNotFound)
+        ));
+        assert_eq!(fs::read_dir(&out.path).unwrap().count(), 0);
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn manifest_lists_extracted_files() {
+        let packed = hsq(b"hello");
+        let mut truncated = hsq(b"hello world");
+        truncate_hsq(&mut truncated, 10);
+
+        let mut dat_file = dat_file(&[
+            ("A.HSQ", &packed),
+            ("BAD.HSQ", &truncated),
+            ("SUB\\B.BIN", b"b"),
+        ]);
+        let out = temp_out_dir("manifest");
+        let manifest = out.path.join("manifest.json");
+
+        extract_all(
+            &out,
+            &mut dat_file,
+            false,
+            Some(&manifest),
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+
+        let manifest = fs::read_to_string(manifest).unwrap();
+        let records = manifest
+            .lines()
+            .filter(|l| l.starts_with("  {"))
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+
+        let a_path = out.path.join("A.BIN");
+        assert!(records[0].starts_with("  {\"name\": \"A.HSQ\""));
+        assert!(records[0].contains(&json_string(&a_path.to_string_lossy())));
+        assert!(records[0].contains("\"unpacked_size\": 5, \"compressed\": true"));
+        assert_eq!(fs::read(a_path).unwrap(), b"hello");
+
+        let b_path = out.path.join("SUB").join("B.BIN");
+        assert!(records[1].starts_with("  {\"name\": \"SUB\\\\B.BIN\""));
+        assert!(records[1].contains(&json_string(&b_path.to_string_lossy())));
+        assert_eq!(fs::read(b_path).unwrap(), b"b");
+
+        assert!(!out.path.join("BAD.BIN").exists());
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    /// A font in the default layout with glyph widths 0, 1, ... 255.
+    fn font() -> Vec<u8> {
+        let layout = FontLayout::default();
+        let mut data = (0..=255).collect::<Vec<u8>>();
+        data.resize(layout.bank2_offset + 128 * layout.bank2_height, 0xff);
+        data
+    }
+
+    #[test]
+    fn widths_option_writes_glyph_widths() {
+        let data = font();
+        let out = temp_out_dir("widths");
+
+        write_font(&data, "FONT.BIN", &out.path, &FontLayout::default(), true).unwrap();
+
+        let json = fs::read_to_string(out.path.join("FONT.widths.json")).unwrap();
+        let widths = json
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(", ")
+            .map(|w| w.parse::<u8>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(widths, data[..256]);
+        assert!(out.path.join("FONT.png").is_file());
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn decompress_sav_needs_no_dat_file() {
+        let out = temp_out_dir("decompress-sav");
+        let sav_path = out.path.join("GAME.SAV");
+        SavFile {
+            unk0: 1,
+            rle_word: 0xf7,
+            data: vec![1, 2, 2, 2, 2, 3],
+        }
+        .save(&sav_path)
+        .unwrap();
+
+        decompress_sav(sav_path.to_str().unwrap()).unwrap();
+
+        let sav = SavFile::load_decompressed(out.path.join("GAME.BIN")).unwrap();
+        assert_eq!(sav.data, [1, 2, 2, 2, 2, 3]);
+        assert!(!out.path.join("DUNE.DAT").exists());
+
+        fs::remove_dir_all(&out.path).unwrap();
+    }
+
+    #[test]
+    fn write_gif_uses_sheet_palette() {
+        let mut pal = vec![0u8; 768];
+        pal[3..6].copy_from_slice(&[63, 0, 0]);
+        pal[6..9].copy_from_slice(&[0, 63, 0]);
+
+        // A 2x1 frame of indices 1 and 2, and a 1x1 transparent frame
+        let frames = [
+            (2, 1, vec![255, 0, 0, 255, 0, 255, 0, 255]),
+            (1, 1, vec![0, 0, 0, 0]),
+        ];
+
+        let path = std::env::temp_dir().join("dune-extract-write-gif-test.gif");
+        write_gif(&path, &frames, &pal, 10).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        assert_eq!(
+            &decoder.global_palette().unwrap()[3..9],
+            [255, 0, 0, 0, 255, 0]
+        );
+
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(frame.transparent, Some(0));
+        assert_eq!(&frame.buffer[..], [1, 2]);
+
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(&frame.buffer[..], [0, 0]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn entry_stem_strips_directory_and_extension() {
+        assert_eq!(entry_stem("SUB\\S.VOC", "SOUND"), "S");
+        assert_eq!(entry_stem("FONT.BIN", "FONT"), "FONT");
+        assert_eq!(entry_stem("SUB\\", "SOUND"), "SOUND");
+        assert_eq!(entry_stem("..", "SOUND"), "SOUND");
+    }
+}
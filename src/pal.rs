@@ -0,0 +1,102 @@
+use std::io::{self, Cursor, Write};
+
+use crate::bytes_ext::ReadBytesExt;
+
+pub fn scale_6bit_to_8bit(c: u8) -> u8 {
+    (255 * (c as u16) / 63) as u8
+}
+
+/// Applies a palette update to a 768-byte palette of 6-bit RGB triplets.
+///
+/// An update is a list of runs, each a byte giving the first palette index
+/// followed by a byte giving the number of colors (0 meaning 256) and the
+/// colors themselves. The list is terminated by 0xffff; running out of data
+/// before the terminator is an error.
+pub fn apply_palette_update(r: &mut Cursor<&[u8]>, pal: &mut [u8]) -> io::Result<()> {
+    loop {
+        let mut v: u16;
+        loop {
+            v = r.read_le_u16_opt()?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "palette update without terminator",
+                )
+            })?;
+            // A run of one color at index 0 is skipped along with its
+            // color, and the next word read in its place
+            if v != 256 {
+                break;
+            }
+            r.set_position(r.position() + 3);
+        }
+        if v == 0xffff {
+            break;
+        }
+
+        let mut count = (v >> 8) & 0xff;
+        let offset = v & 0xff;
+
+        if count == 0 {
+            count = 256;
+        }
+
+        if 3 * (offset + count) as usize > pal.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "palette update past the end of the palette",
+            ));
+        }
+
+        for i in 0..3 * count {
+            pal[(3 * offset + i) as usize] = r.read_u8()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a 768-byte palette of 6-bit RGB triplets as a GIMP palette.
+pub fn write_gpl<W: Write>(w: &mut W, name: &str, pal: &[u8]) -> io::Result<()> {
+    writeln!(w, "GIMP Palette")?;
+    writeln!(w, "Name: {}", name)?;
+    writeln!(w, "Columns: 16")?;
+    writeln!(w, "#")?;
+
+    for (i, rgb) in pal.chunks_exact(3).enumerate() {
+        writeln!(
+            w,
+            "{:3} {:3} {:3}\tIndex {}",
+            scale_6bit_to_8bit(rgb[0]),
+            scale_6bit_to_8bit(rgb[1]),
+            scale_6bit_to_8bit(rgb[2]),
+            i
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_single_color_run_at_index_0() {
+        let data = [0x00, 0x01, 1, 2, 3, 0x02, 0x01, 4, 5, 6, 0xff, 0xff];
+        let mut pal = vec![0; 768];
+
+        apply_palette_update(&mut Cursor::new(&data[..]), &mut pal).unwrap();
+        assert_eq!(pal[..9], [0, 0, 0, 0, 0, 0, 4, 5, 6]);
+        assert!(pal[9..].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn rejects_update_without_terminator() {
+        let data = [0x01, 0x01, 10, 20, 30];
+        let mut pal = vec![0; 768];
+
+        let err = apply_palette_update(&mut Cursor::new(&data[..]), &mut pal).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(pal[3..6], [10, 20, 30]);
+    }
+}
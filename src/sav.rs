@@ -0,0 +1,194 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Cursor, Read, Write},
+    path::Path,
+    slice,
+};
+
+use crate::{
+    bytes_ext::{ReadBytesExt, WriteBytesExt},
+    error::Error,
+};
+
+/// A save game. Save files are stored RLE-compressed with a three word
+/// header; decompressed saves keep the first two words of that header.
+pub struct SavFile {
+    pub unk0: u16,
+    pub rle_word: u16,
+    /// The decompressed save data following the header
+    pub data: Vec<u8>,
+}
+
+impl SavFile {
+    /// Reads an RLE-compressed save file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SavFile, Error> {
+        let data = fs::read(path)?;
+        let data_len = data.len();
+
+        let mut r = Cursor::new(data);
+
+        let unk0 = r.read_le_u16()?;
+        let rle_word = r.read_le_u16()?;
+        // The length includes the rle-word and itself but not the first word
+        let length = r.read_le_u16()? as usize;
+
+        let rle_byte = rle_word as u8;
+
+        if length != data_len - 2 {
+            return Err(Error::InvalidSaveFile("invalid length in header"));
+        }
+
+        let mut w = Vec::<u8>::new();
+
+        while let Some(c) = r.read_u8_opt()? {
+            if c == rle_byte {
+                let cnt = r.read_u8()?;
+                let val = r.read_u8()?;
+                for _ in 0..cnt {
+                    w.write_all(slice::from_ref(&val))?;
+                }
+            } else {
+                w.write_all(slice::from_ref(&c))?;
+            }
+        }
+
+        Ok(SavFile {
+            unk0,
+            rle_word,
+            data: w,
+        })
+    }
+
+    /// Reads a save file previously written by `save_decompressed`.
+    pub fn load_decompressed<P: AsRef<Path>>(path: P) -> Result<SavFile, Error> {
+        let mut r = File::open(path)?;
+
+        let unk0 = r.read_le_u16()?;
+        let rle_word = r.read_le_u16()?;
+
+        if rle_word as u8 != 0xf7 {
+            return Err(Error::InvalidSaveFile("invalid rle byte"));
+        }
+
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        Ok(SavFile {
+            unk0,
+            rle_word,
+            data,
+        })
+    }
+
+    /// Writes the save RLE-compressed, as the game expects.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut w = Vec::<u8>::new();
+        rle_compress_for_save_files(&mut self.data.as_slice(), &mut w, self.rle_word as u8)?;
+
+        let mut out_file = BufWriter::new(File::create(path)?);
+        out_file.write_le_u16(self.unk0)?;
+        out_file.write_le_u16(self.rle_word)?;
+        out_file.write_le_u16((w.len() + 4) as u16)?;
+        out_file.write_all(&w)?;
+
+        Ok(())
+    }
+
+    pub fn save_decompressed<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut out_file = BufWriter::new(File::create(path)?);
+        out_file.write_le_u16(self.unk0)?;
+        out_file.write_le_u16(self.rle_word)?;
+        out_file.write_all(&self.data)?;
+
+        Ok(())
+    }
+}
+
+fn rle_compress_for_save_files<R: Read, W: Write>(
+    r: &mut R,
+    w: &mut W,
+    rle_byte: u8,
+) -> std::io::Result<()> {
+    #[derive(Default)]
+    struct State {
+        v: u8,
+        reps: usize,
+    }
+
+    impl State {
+        fn new(v: u8) -> State {
+            State { v, reps: 1 }
+        }
+    }
+
+    let mut state = State::default();
+
+    let mut output = |state: &mut State| -> std::io::Result<()> {
+        if state.reps > 2 || state.v == 0xf7 {
+            while state.reps > 0 {
+                w.write_u8(rle_byte)?;
+                w.write_u8(state.reps.min(255) as u8)?;
+                w.write_u8(state.v)?;
+                state.reps -= state.reps.min(255);
+            }
+        } else {
+            while state.reps > 0 {
+                w.write_u8(state.v)?;
+                state.reps -= 1;
+            }
+        }
+
+        Ok(())
+    };
+
+    while let Some(b) = r.read_u8_opt()? {
+        if state.reps == 0 {
+            state = State::new(b);
+        } else if state.v == b {
+            state.reps += 1;
+        } else {
+            output(&mut state)?;
+            state = State::new(b);
+        }
+
+        if state.v == rle_byte {
+            output(&mut state)?;
+        }
+    }
+
+    output(&mut state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_save_reloads_with_new_value() {
+        let mut data = vec![1, 2, 3, 3, 3, 3, 0xf7, 0xf7, 0xf7, 5, 0xf7, 6];
+        data.resize(600, 0);
+        data.push(7);
+
+        let path = std::env::temp_dir().join("dune-extract-sav-test.SAV");
+        SavFile {
+            unk0: 0x1234,
+            rle_word: 0x00f7,
+            data: data.clone(),
+        }
+        .save(&path)
+        .unwrap();
+
+        let mut sav = SavFile::load(&path).unwrap();
+        assert_eq!((sav.unk0, sav.rle_word), (0x1234, 0x00f7));
+        assert_eq!(sav.data, data);
+
+        sav.data[1] = 42;
+        sav.save(&path).unwrap();
+
+        let sav = SavFile::load(&path).unwrap();
+        data[1] = 42;
+        assert_eq!(sav.data, data);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,9 +1,35 @@
 use std::io::{self, Cursor};
 
-use crate::bytes_ext::ReadBytesExt;
+use crate::{bytes_ext::ReadBytesExt, pal::scale_6bit_to_8bit};
 
-fn scale_6bit_to_8bit(c: u8) -> u8 {
-    (255 * (c as u16) / 63) as u8
+/// The four byte header preceding each sprite in a sprite sheet.
+pub struct SpriteHeader {
+    pub width: usize,
+    pub height: usize,
+    pub pal_offset: u8,
+    pub is_rle_compressed: bool,
+}
+
+impl SpriteHeader {
+    pub fn read(r: &mut Cursor<&[u8]>) -> io::Result<SpriteHeader> {
+        let w0 = r.read_le_u16()?;
+        let w1 = r.read_le_u16()?;
+
+        let flags = ((w0 & 0xff00) >> 8) as u8;
+
+        Ok(SpriteHeader {
+            width: (w0 & 0x7fff) as usize,
+            height: (w1 & 0x00ff) as usize,
+            pal_offset: ((w1 & 0xff00) >> 8) as u8,
+            is_rle_compressed: flags & 0x80 != 0,
+        })
+    }
+
+    /// Sprites with a palette offset below 254 are stored with 4 bits per
+    /// pixel.
+    pub fn is_4bpp(&self) -> bool {
+        self.pal_offset < 254
+    }
 }
 
 fn write_pixel(dst: &mut [u8], w: usize, x: usize, y: usize, pal: &[u8], c: u8) {
@@ -21,22 +47,23 @@ pub fn draw_4bpp(
     h: usize,
     pal: &[u8],
     mode: u8,
+    transparent: Option<u8>,
 ) -> io::Result<()> {
     for y in 0..h {
-        let mut line_remain = 4 * ((w + 3) / 4);
+        let mut line_remain = 4 * w.div_ceil(4);
         let mut x = 0;
         while line_remain > 0 {
             let value = src.read_u8()?;
             let p1 = value & 0x0f;
             let p2 = value >> 4;
 
-            if p1 != 0 && x < w {
-                write_pixel(dst, w, x, y, pal, p1 + mode);
+            if Some(p1) != transparent && x < w {
+                write_pixel(dst, w, x, y, pal, p1.wrapping_add(mode));
             }
             x += 1;
 
-            if p2 != 0 && x < w {
-                write_pixel(dst, w, x, y, pal, p2 + mode);
+            if Some(p2) != transparent && x < w {
+                write_pixel(dst, w, x, y, pal, p2.wrapping_add(mode));
             }
             x += 1;
 
@@ -46,6 +73,14 @@ pub fn draw_4bpp(
     Ok(())
 }
 
+/// Counts a run of `count` bytes against the pixels left in a line, failing
+/// if the run extends past its end.
+fn next_line_remain(line_remain: usize, count: u16) -> io::Result<usize> {
+    line_remain
+        .checked_sub(2 * count as usize)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "sprite run past end of line"))
+}
+
 pub fn draw_4bpp_rle(
     dst: &mut [u8],
     src: &mut Cursor<&[u8]>,
@@ -53,9 +88,10 @@ pub fn draw_4bpp_rle(
     h: usize,
     pal: &[u8],
     mode: u8,
+    transparent: Option<u8>,
 ) -> io::Result<()> {
     for y in 0..h {
-        let mut line_remain = 4 * ((w + 3) / 4);
+        let mut line_remain = 4 * w.div_ceil(4);
         let mut x = 0;
         while line_remain > 0 {
             let cmd = src.read_u8()?;
@@ -66,16 +102,16 @@ pub fn draw_4bpp_rle(
                 let p1 = value & 0x0f;
                 let p2 = value >> 4;
                 for _ in 0..count {
-                    if p1 != 0 {
-                        write_pixel(dst, w, x, y, pal, p1 + mode);
+                    if Some(p1) != transparent && x < w {
+                        write_pixel(dst, w, x, y, pal, p1.wrapping_add(mode));
                     }
                     x += 1;
-                    if p2 != 0 {
-                        write_pixel(dst, w, x, y, pal, p2 + mode);
+                    if Some(p2) != transparent && x < w {
+                        write_pixel(dst, w, x, y, pal, p2.wrapping_add(mode));
                     }
                     x += 1;
                 }
-                line_remain -= 2 * (count as usize);
+                line_remain = next_line_remain(line_remain, count)?;
             } else {
                 let count = (cmd + 1) as u16;
                 for _ in 0..count {
@@ -84,17 +120,17 @@ pub fn draw_4bpp_rle(
                     let p1 = value & 0x0f;
                     let p2 = value >> 4;
 
-                    if p1 != 0 {
-                        write_pixel(dst, w, x, y, pal, p1 + mode);
+                    if Some(p1) != transparent && x < w {
+                        write_pixel(dst, w, x, y, pal, p1.wrapping_add(mode));
                     }
                     x += 1;
 
-                    if p2 != 0 {
-                        write_pixel(dst, w, x, y, pal, p2 + mode);
+                    if Some(p2) != transparent && x < w {
+                        write_pixel(dst, w, x, y, pal, p2.wrapping_add(mode));
                     }
                     x += 1;
                 }
-                line_remain -= 2 * (count as usize);
+                line_remain = next_line_remain(line_remain, count)?;
             }
         }
     }
@@ -107,12 +143,12 @@ pub fn draw_8bpp(
     w: usize,
     h: usize,
     pal: &[u8],
-    mode: u8,
+    transparent: Option<u8>,
 ) -> io::Result<()> {
     for y in 0..h {
         for x in 0..w {
             let value = src.read_u8()?;
-            if mode != 255 && value != 0 {
+            if Some(value) != transparent {
                 write_pixel(dst, w, x, y, pal, value);
             }
         }
@@ -126,7 +162,7 @@ pub fn draw_8bpp_rle(
     w: usize,
     h: usize,
     pal: &[u8],
-    mode: u8,
+    transparent: Option<u8>,
 ) -> io::Result<()> {
     for y in 0..h {
         let mut x = 0;
@@ -137,7 +173,7 @@ pub fn draw_8bpp_rle(
                 let count = 257 - (cmd as u16);
                 let value = src.read_u8()?;
                 for _ in 0..count {
-                    if (mode != 255 || value != 0) && x < w {
+                    if Some(value) != transparent && x < w {
                         write_pixel(dst, w, x, y, pal, value);
                     }
                     x += 1;
@@ -146,7 +182,7 @@ pub fn draw_8bpp_rle(
                 let count = (cmd + 1) as u16;
                 for _ in 0..count {
                     let value = src.read_u8()?;
-                    if (mode != 255 || value != 0) && x < w {
+                    if Some(value) != transparent && x < w {
                         write_pixel(dst, w, x, y, pal, value);
                     }
                     x += 1;
@@ -156,3 +192,99 @@ pub fn draw_8bpp_rle(
     }
     Ok(())
 }
+
+pub fn flip_x(dst: &mut [u8], w: usize, h: usize) {
+    for y in 0..h {
+        let line = &mut dst[4 * y * w..4 * (y + 1) * w];
+        for x in 0..w / 2 {
+            for c in 0..4 {
+                line.swap(4 * x + c, 4 * (w - 1 - x) + c);
+            }
+        }
+    }
+}
+
+pub fn flip_y(dst: &mut [u8], w: usize, h: usize) {
+    for y in 0..h / 2 {
+        let (top, bottom) = dst.split_at_mut(4 * (h - 1 - y) * w);
+        top[4 * y * w..4 * (y + 1) * w].swap_with_slice(&mut bottom[..4 * w]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_palette() -> Vec<u8> {
+        (0..768).map(|i| (i / 3) as u8 % 64).collect()
+    }
+
+    fn alpha(dst: &[u8]) -> Vec<u8> {
+        dst.chunks_exact(4).map(|p| p[3]).collect()
+    }
+
+    #[test]
+    fn draw_4bpp_rle_clips_padding_pixels() {
+        // 3x2, each line a literal run of two bytes; the fourth nibble is
+        // padding
+        let src = [0x01, 0x21, 0x43, 0x01, 0x21, 0x43];
+        let mut dst = vec![0; 3 * 2 * 4];
+        draw_4bpp_rle(
+            &mut dst,
+            &mut Cursor::new(&src),
+            3,
+            2,
+            &gray_palette(),
+            0,
+            None,
+        )
+        .unwrap();
+        assert_eq!(alpha(&dst), [255; 6]);
+    }
+
+    #[test]
+    fn draw_4bpp_rle_rejects_run_past_end_of_line() {
+        let mut src = vec![0x7f];
+        src.extend_from_slice(&[0; 128]);
+        let mut dst = vec![0; 3 * 4];
+        let err = draw_4bpp_rle(
+            &mut dst,
+            &mut Cursor::new(&src),
+            3,
+            1,
+            &gray_palette(),
+            0,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn draw_skips_transparent_index() {
+        let src = [5, 1, 0, 5];
+        let mut dst = vec![0; 4 * 4];
+        draw_8bpp(
+            &mut dst,
+            &mut Cursor::new(&src),
+            4,
+            1,
+            &gray_palette(),
+            Some(5),
+        )
+        .unwrap();
+        assert_eq!(alpha(&dst), [0, 255, 255, 0]);
+
+        let mut dst = vec![0; 4 * 4];
+        draw_8bpp(
+            &mut dst,
+            &mut Cursor::new(&src),
+            4,
+            1,
+            &gray_palette(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(alpha(&dst), [255; 4]);
+    }
+}
@@ -1,6 +1,9 @@
-use std::io::Cursor;
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor},
+};
 
-use crate::bytes_ext::ReadBytesExt;
+use crate::{bytes_ext::ReadBytesExt, error::Error};
 
 struct Reader<'a> {
     queue: u16,
@@ -8,62 +11,269 @@ struct Reader<'a> {
 }
 
 impl Reader<'_> {
-    pub fn read_bit(&mut self) -> bool {
+    pub fn read_bit(&mut self) -> io::Result<bool> {
         let mut queue = self.queue;
         let mut bit = (queue & 1) == 1;
         queue >>= 1;
         if queue == 0 {
-            queue = self.r.read_le_u16().unwrap();
+            queue = self.r.read_le_u16()?;
             bit = (queue & 1) == 1;
             queue = 0x8000 | (queue >> 1);
         }
         self.queue = queue;
-        bit
+        Ok(bit)
     }
-    pub fn read_u8(&mut self) -> u8 {
-        self.r.read_u8().unwrap()
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        self.r.read_u8()
     }
-    pub fn read_le_u16(&mut self) -> u16 {
-        self.r.read_le_u16().unwrap()
+    pub fn read_le_u16(&mut self) -> io::Result<u16> {
+        self.r.read_le_u16()
     }
 }
 
-pub fn unhsq(r: &[u8], w: &mut [u8]) {
+/// Counts of the commands in an HSQ stream.
+#[derive(Debug, Default)]
+pub struct HsqStats {
+    pub literals: usize,
+    pub short_matches: usize,
+    pub long_matches: usize,
+    /// Number of matches by copy length
+    pub match_lengths: BTreeMap<u16, usize>,
+    /// Number of matches by back-reference offset, rounded up to a power of two
+    pub match_offsets: BTreeMap<u16, usize>,
+}
+
+impl HsqStats {
+    fn add_match(&mut self, count: u16, offset: u16) {
+        *self.match_lengths.entry(count).or_default() += 1;
+        *self
+            .match_offsets
+            .entry(offset.next_power_of_two())
+            .or_default() += 1;
+    }
+}
+
+pub fn unhsq(r: &[u8], w: &mut [u8]) -> Result<(), Error> {
+    unhsq_impl(r, w, None)
+}
+
+/// Decompresses like `unhsq`, also counting the commands in the stream.
+pub fn unhsq_with_stats(r: &[u8], w: &mut [u8]) -> Result<HsqStats, Error> {
+    let mut stats = HsqStats::default();
+    unhsq_impl(r, w, Some(&mut stats))?;
+    Ok(stats)
+}
+
+fn unhsq_impl(r: &[u8], w: &mut [u8], stats: Option<&mut HsqStats>) -> Result<(), Error> {
     let mut r = Reader {
         queue: 0,
         r: Cursor::new(r),
     };
-    let mut w_ofs: u16 = 0;
+
+    match decode(&mut r, w, stats) {
+        Err(Error::IOError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(Error::InvalidHsqData("truncated stream"))
+        }
+        result => result,
+    }
+}
+
+fn decode(r: &mut Reader, w: &mut [u8], mut stats: Option<&mut HsqStats>) -> Result<(), Error> {
+    let mut w_ofs: usize = 0;
 
     loop {
-        if r.read_bit() {
-            w[w_ofs as usize] = r.read_u8();
+        if r.read_bit()? {
+            if w_ofs >= w.len() {
+                return Err(Error::InvalidHsqData("output overflow"));
+            }
+            w[w_ofs] = r.read_u8()?;
             w_ofs += 1;
+
+            if let Some(stats) = stats.as_mut() {
+                stats.literals += 1;
+            }
         } else {
             let mut count: u16;
             let offset: u16;
-            if r.read_bit() {
-                let word = r.read_le_u16();
+            if r.read_bit()? {
+                let word = r.read_le_u16()?;
                 count = word & 7;
                 offset = 8192 - (word >> 3);
                 if count == 0 {
-                    count = r.read_u8() as u16;
+                    count = r.read_u8()? as u16;
                 }
                 if count == 0 {
                     break;
                 }
+
+                if let Some(stats) = stats.as_mut() {
+                    stats.long_matches += 1;
+                    stats.add_match(count + 2, offset);
+                }
             } else {
-                let b0 = r.read_bit() as u16;
-                let b1 = r.read_bit() as u16;
+                let b0 = r.read_bit()? as u16;
+                let b1 = r.read_bit()? as u16;
 
                 count = 2 * b0 + b1;
-                offset = 256 - (r.read_u8() as u16);
+                offset = 256 - (r.read_u8()? as u16);
+
+                if let Some(stats) = stats.as_mut() {
+                    stats.short_matches += 1;
+                    stats.add_match(count + 2, offset);
+                }
+            }
+
+            let offset = offset as usize;
+            let count = count as usize + 2;
+            if offset > w_ofs {
+                return Err(Error::InvalidHsqData("offset before start of output"));
+            }
+            if w_ofs + count > w.len() {
+                return Err(Error::InvalidHsqData("output overflow"));
             }
 
-            for _ in 0..count + 2 {
-                w[w_ofs as usize] = w[(w_ofs - offset) as usize];
+            for _ in 0..count {
+                w[w_ofs] = w[w_ofs - offset];
                 w_ofs += 1;
             }
         }
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writes an HSQ stream: control bits are packed into little-endian
+    /// words placed ahead of the bytes read after them.
+    #[derive(Default)]
+    struct Encoder {
+        out: Vec<u8>,
+        word_pos: usize,
+        bits: usize,
+    }
+
+    impl Encoder {
+        fn bit(&mut self, b: bool) {
+            if self.bits.is_multiple_of(16) {
+                self.word_pos = self.out.len();
+                self.out.extend_from_slice(&[0, 0]);
+            }
+            if b {
+                let bit = self.bits % 16;
+                self.out[self.word_pos + bit / 8] |= 1 << (bit % 8);
+            }
+            self.bits += 1;
+        }
+
+        fn literal(&mut self, v: u8) {
+            self.bit(true);
+            self.out.push(v);
+        }
+
+        fn short_match(&mut self, count: u16, offset: u16) {
+            self.bit(false);
+            self.bit(false);
+            self.bit(count & 2 != 0);
+            self.bit(count & 1 != 0);
+            self.out.push((256 - offset) as u8);
+        }
+
+        fn end(mut self) -> Vec<u8> {
+            self.bit(false);
+            self.bit(true);
+            self.out.extend_from_slice(&[0, 0, 0]);
+            self.out
+        }
+    }
+
+    /// Compresses `data` as literals, with a valid six byte header.
+    pub(crate) fn hsq(data: &[u8]) -> Vec<u8> {
+        let mut e = Encoder::default();
+        for &c in data {
+            e.literal(c);
+        }
+        let stream = e.end();
+
+        let packed_length = (stream.len() + 6) as u16;
+        let mut out = Vec::new();
+        out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        out.push(0);
+        out.extend_from_slice(&packed_length.to_le_bytes());
+        let sum = out.iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+        out.push(0xabu8.wrapping_sub(sum));
+        out.extend_from_slice(&stream);
+        out
+    }
+
+    /// Cuts a stream from `hsq` down to `len` bytes, keeping its header
+    /// valid.
+    pub(crate) fn truncate_hsq(packed: &mut Vec<u8>, len: usize) {
+        packed.truncate(len);
+        packed[3..5].copy_from_slice(&(len as u16).to_le_bytes());
+        let sum = packed[..5].iter().fold(0u8, |acc, &x| acc.wrapping_add(x));
+        packed[5] = 0xabu8.wrapping_sub(sum);
+    }
+
+    #[test]
+    fn decodes_literals_and_matches() {
+        let mut e = Encoder::default();
+        for &c in b"abc" {
+            e.literal(c);
+        }
+        e.short_match(1, 3);
+        let stream = e.end();
+
+        let mut w = [0; 6];
+        unhsq(&stream, &mut w).unwrap();
+        assert_eq!(&w, b"abcabc");
+    }
+
+    #[test]
+    fn rejects_offset_before_start_of_output() {
+        let mut e = Encoder::default();
+        e.literal(b'a');
+        e.short_match(0, 10);
+        let stream = e.end();
+
+        let mut w = [0; 3];
+        assert!(matches!(
+            unhsq(&stream, &mut w),
+            Err(Error::InvalidHsqData("offset before start of output"))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_stream() {
+        let mut e = Encoder::default();
+        for &c in b"abcdef" {
+            e.literal(c);
+        }
+        let stream = e.end();
+
+        let mut w = [0; 6];
+        assert!(matches!(
+            unhsq(&stream[..4], &mut w),
+            Err(Error::InvalidHsqData("truncated stream"))
+        ));
+    }
+
+    #[test]
+    fn decodes_more_than_64k() {
+        let len = 2 + 5 * 14000;
+
+        let mut e = Encoder::default();
+        e.literal(1);
+        e.literal(2);
+        for _ in 0..(len - 2) / 5 {
+            e.short_match(3, 2);
+        }
+        let stream = e.end();
+
+        let mut w = vec![0; len];
+        unhsq(&stream, &mut w).unwrap();
+        assert!(w.chunks(2).all(|c| c == [1, 2]));
+    }
 }
@@ -0,0 +1,165 @@
+use std::io::{self, Cursor, Read, Write};
+
+use crate::{
+    bytes_ext::{ReadBytesExt, WriteBytesExt},
+    error::Error,
+};
+
+const VOC_SIGNATURE: &[u8] = b"Creative Voice File\x1a";
+
+/// Unsigned 8-bit mono PCM samples.
+pub struct Sound {
+    pub sample_rate: u32,
+    pub samples: Vec<u8>,
+}
+
+pub fn is_voc(data: &[u8]) -> bool {
+    data.starts_with(VOC_SIGNATURE)
+}
+
+fn sample_rate_from_divisor(divisor: u8) -> u32 {
+    1_000_000 / (256 - divisor as u32)
+}
+
+fn read_le_u24(r: &mut Cursor<&[u8]>) -> io::Result<u32> {
+    let lo = r.read_le_u16()? as u32;
+    let hi = r.read_u8()? as u32;
+    Ok(lo | (hi << 16))
+}
+
+pub fn decode_voc(data: &[u8]) -> Result<Sound, Error> {
+    if !is_voc(data) {
+        return Err(Error::InvalidVocFile("missing signature"));
+    }
+
+    let mut r = Cursor::new(data);
+    r.set_position(VOC_SIGNATURE.len() as u64);
+
+    let header_size = r.read_le_u16()?;
+    r.set_position(header_size as u64);
+
+    let mut sample_rate = None;
+    let mut samples = Vec::new();
+
+    loop {
+        let block_type = match r.read_u8_opt()? {
+            Some(0) | None => break,
+            Some(t) => t,
+        };
+        let block_size = read_le_u24(&mut r)? as u64;
+        let block_end = r.position() + block_size;
+
+        match block_type {
+            // Sound data
+            1 => {
+                let divisor = r.read_u8()?;
+                let codec = r.read_u8()?;
+                if codec != 0 {
+                    return Err(Error::InvalidVocFile("unsupported codec"));
+                }
+                sample_rate.get_or_insert(sample_rate_from_divisor(divisor));
+
+                let mut block = vec![0; block_size.saturating_sub(2) as usize];
+                r.read_exact(&mut block)?;
+                samples.extend_from_slice(&block);
+            }
+            // Sound continuation
+            2 => {
+                let mut block = vec![0; block_size as usize];
+                r.read_exact(&mut block)?;
+                samples.extend_from_slice(&block);
+            }
+            // Silence
+            3 => {
+                let length = r.read_le_u16()? as usize + 1;
+                let divisor = r.read_u8()?;
+                sample_rate.get_or_insert(sample_rate_from_divisor(divisor));
+
+                samples.resize(samples.len() + length, 0x80);
+            }
+            _ => {}
+        }
+
+        r.set_position(block_end);
+    }
+
+    let Some(sample_rate) = sample_rate else {
+        return Err(Error::InvalidVocFile("no sound data"));
+    };
+
+    Ok(Sound {
+        sample_rate,
+        samples,
+    })
+}
+
+pub fn write_wav<W: Write>(w: &mut W, sound: &Sound) -> io::Result<()> {
+    let data_size = sound.samples.len() as u32;
+
+    w.write_all(b"RIFF")?;
+    w.write_le_u32(36 + data_size)?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_le_u32(16)?;
+    w.write_le_u16(1)?; // PCM
+    w.write_le_u16(1)?; // Mono
+    w.write_le_u32(sound.sample_rate)?;
+    w.write_le_u32(sound.sample_rate)?; // Byte rate
+    w.write_le_u16(1)?; // Block align
+    w.write_le_u16(8)?; // Bits per sample
+
+    w.write_all(b"data")?;
+    w.write_le_u32(data_size)?;
+    w.write_all(&sound.samples)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a VOC file from `(type, data)` blocks.
+    fn voc(blocks: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut out = VOC_SIGNATURE.to_vec();
+        out.extend_from_slice(&[26, 0, 0x0a, 0x01, 0x29, 0x11]);
+        for (block_type, data) in blocks {
+            out.push(*block_type);
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+            out.extend_from_slice(data);
+        }
+        out.push(0);
+        out
+    }
+
+    #[test]
+    fn decodes_sound_data_blocks() {
+        let data = voc(&[(1, &[156, 0, 1, 2, 3, 4]), (2, &[5, 6, 7])]);
+
+        let sound = decode_voc(&data).unwrap();
+        assert_eq!(sound.sample_rate, 10000);
+        assert_eq!(sound.samples, [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn decodes_silence_blocks() {
+        let data = voc(&[(3, &[9, 0, 156]), (1, &[0, 0, 1, 2])]);
+
+        let sound = decode_voc(&data).unwrap();
+        assert_eq!(sound.sample_rate, 10000);
+        assert_eq!(sound.samples.len(), 12);
+        assert!(sound.samples[..10].iter().all(|&s| s == 0x80));
+        assert_eq!(sound.samples[10..], [1, 2]);
+    }
+
+    #[test]
+    fn rejects_compressed_sound_data() {
+        let data = voc(&[(1, &[156, 1, 1, 2])]);
+
+        assert!(matches!(
+            decode_voc(&data),
+            Err(Error::InvalidVocFile("unsupported codec"))
+        ));
+    }
+}